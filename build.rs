@@ -0,0 +1,39 @@
+use std::env;
+use std::path::Path;
+use std::process::{self, Command};
+
+const TOOL: &str = "glib-compile-resources";
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    eprintln!("{} comes with the GLib development tools, e.g. the libglib2.0-dev-bin \
+               package on Debian and Ubuntu or glib2-devel on Fedora. See README.md.", TOOL);
+    process::exit(1);
+}
+
+// Compiles ui/ and assets/ into a GResource bundle that src/resources.rs
+// embeds in the binary.
+fn main() {
+    // Only the GTK application uses the bundle.
+    if env::var_os("CARGO_FEATURE_GUI").is_none() {
+        return;
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let target = Path::new(&out_dir).join("vanilla_text.gresource");
+
+    let status = Command::new(TOOL)
+        .arg("--sourcedir=.")
+        .arg(format!("--target={}", target.display()))
+        .arg("vanilla_text.gresource.xml")
+        .status();
+    match status {
+        Ok(ref s) if s.success() => {},
+        Ok(s) => fail(&format!("{} failed ({})", TOOL, s)),
+        Err(e) => fail(&format!("cannot run {}: {}", TOOL, e)),
+    }
+
+    println!("cargo:rerun-if-changed=vanilla_text.gresource.xml");
+    println!("cargo:rerun-if-changed=ui");
+    println!("cargo:rerun-if-changed=assets");
+}
@@ -0,0 +1,325 @@
+extern crate gtk;
+extern crate gio;
+extern crate glib;
+
+use std::env::Args;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use gtk::{ GtkApplicationExt, WindowExt, DialogExt, WidgetExt, Cast, Continue };
+
+use gio::{ 
+    ApplicationExt, ApplicationExtManual,
+    ActionMapExt, SimpleActionExt, FileExt
+};
+
+use glib::ToVariant;
+
+use location;
+use recovery;
+use session;
+use recent;
+use resources;
+use keymap;
+use palette;
+use prefs;
+use win::{
+    Window, WindowExtend, Windows, WindowsExtend
+};
+use prefs::{BackupMode, Preferences};
+use prefs_dialog::PrefsDialog;
+use shortcuts_dialog::ShortcutsDialog;
+use page::PageExtend;
+
+fn init_actions(app: &gtk::Application, wins: &Windows) {
+    let new_window_action = gio::SimpleAction::new("new_window", None);
+    {
+        let app = app.clone();
+        let wins = wins.clone();
+        new_window_action.connect_activate(move |_, _| {
+            let w = Window::create(&app, wins.clone());
+            w.init();
+        });
+    }
+
+    let quit_action = gio::SimpleAction::new("quit", None);
+    {
+        let app = app.clone();
+        let wins = wins.clone();
+        quit_action.connect_activate(move |_, _| {
+            session::save(&wins);
+            app.quit();
+        });
+    }
+
+    let backup_mode_action = gio::SimpleAction::new_stateful("backup_mode",
+                                                             glib::VariantTy::new("s").ok(),
+                                                             &prefs::get().backup.id().to_variant());
+    backup_mode_action.connect_change_state(move |action, value| {
+        if let Some(mode) = value.as_ref().and_then(|v| v.get_str()).and_then(BackupMode::from_id) {
+            let mut p = prefs::get();
+            p.backup = mode;
+            prefs::set(p);
+            action.set_state(&mode.id().to_variant());
+        }
+    });
+
+    let autosave_action = gio::SimpleAction::new_stateful("autosave", None, &prefs::get().autosave.to_variant());
+    autosave_action.connect_activate(move |action, _| {
+        let mut p = prefs::get();
+        p.autosave = !p.autosave;
+        action.set_state(&p.autosave.to_variant());
+        prefs::set(p);
+    });
+
+    let line_numbers_action = gio::SimpleAction::new_stateful("line_numbers", None, &prefs::get().line_numbers.to_variant());
+    {
+        let app = app.clone();
+        let wins = wins.clone();
+        line_numbers_action.connect_activate(move |_, _| {
+            let mut p = prefs::get();
+            p.line_numbers = !p.line_numbers;
+            prefs::set(p.clone());
+            apply_prefs(&app, &wins, &p);
+        });
+    }
+
+    let preferences_action = gio::SimpleAction::new("preferences", None);
+    {
+        let app = app.clone();
+        let wins = wins.clone();
+        preferences_action.connect_activate(move |_, _| {
+            let dialog = PrefsDialog::new(app.get_active_window().as_ref());
+            let app = app.clone();
+            let wins = wins.clone();
+            dialog.connect(move |p| apply_prefs(&app, &wins, p));
+            dialog.show();
+        });
+    }
+
+    let shortcuts_action = gio::SimpleAction::new("shortcuts", None);
+    {
+        let app = app.clone();
+        let wins = wins.clone();
+        shortcuts_action.connect_activate(move |_, _| {
+            let win = wins.get_active_window(&app).map(|w| w.win());
+            let dialog = ShortcutsDialog::new(&app, win);
+            dialog.connect();
+            dialog.show();
+        });
+    }
+
+    let clear_recent_action = gio::SimpleAction::new("clear_recent", None);
+    clear_recent_action.connect_activate(move |_, _| {
+        recent::clear();
+    });
+
+    app.add_action(&new_window_action);
+    app.add_action(&quit_action);
+    app.add_action(&backup_mode_action);
+    app.add_action(&autosave_action);
+    app.add_action(&line_numbers_action);
+    app.add_action(&clear_recent_action);
+    app.add_action(&preferences_action);
+    app.add_action(&shortcuts_action);
+}
+
+fn set_action_state(app: &gtk::Application, name: &str, state: &glib::Variant) {
+    if let Some(action) = app.lookup_action(name) {
+        if let Ok(action) = action.downcast::<gio::SimpleAction>() {
+            action.set_state(state);
+        }
+    }
+}
+
+fn apply_prefs(app: &gtk::Application, wins: &Windows, p: &Preferences) {
+    set_action_state(app, "backup_mode", &p.backup.id().to_variant());
+    set_action_state(app, "autosave", &p.autosave.to_variant());
+    set_action_state(app, "line_numbers", &p.line_numbers.to_variant());
+    wins.apply_prefs(p);
+}
+
+fn offer_recovery(app: &gtk::Application, wins: &Windows) -> bool {
+    let entries = recovery::list();
+    if entries.is_empty() {
+        return false;
+    }
+
+    let msg = format!("{} unsaved document(s) from a previous session can be recovered. Restore them?", entries.len());
+    let dialog = gtk::MessageDialog::new(None::<&gtk::Window>,
+                                         gtk::DIALOG_MODAL,
+                                         gtk::MessageType::Question,
+                                         gtk::ButtonsType::None,
+                                         &msg);
+    dialog.add_button("Discard", gtk::ResponseType::Reject.into());
+    dialog.add_button("Restore", gtk::ResponseType::Accept.into());
+
+    let restore = dialog.run() == gtk::ResponseType::Accept.into();
+    dialog.destroy();
+
+    let mut current: Option<(usize, Window)> = None;
+    for (entry, text) in entries {
+        if restore {
+            let w = match current {
+                Some((i, ref w)) if i == entry.window => w.clone(),
+                _ => {
+                    let w = Window::create(app, wins.clone());
+                    w.init();
+                    w
+                },
+            };
+            current = Some((entry.window, w.clone()));
+
+            let page = w.get_empty_page().unwrap_or_else(|| w.create_new_page(wins.clone()));
+            page.restore(&entry, &text);
+            w.present(page);
+            w.win().set_title(&entry.title);
+        }
+        recovery::remove(&entry.id);
+    }
+
+    restore
+}
+
+pub const APP_ID: &str = "com.github.koji-m.vanilla_text";
+
+// Connects the application's signals. The returned list holds its windows.
+pub fn build(app: &gtk::Application) -> Windows {
+    let wins = Rc::new(RefCell::new(Vec::<Window>::new()));
+
+    {
+        let wins = wins.clone();
+        app.connect_startup(move |app| {
+            prefs::load();
+            recent::load();
+            init_actions(app, &wins);
+            let icons: Vec<_> = ["icon_48x48.png", "icon_64x64.png", "icon_128x128.png"].iter()
+                .filter_map(|name| resources::pixbuf(name))
+                .collect();
+            gtk::Window::set_default_icon_list(&icons);
+
+            keymap::load();
+            keymap::apply(app);
+            let builder = resources::builder("menu.ui");
+
+            let app_menu: gio::Menu = builder.get_object("app_menu").unwrap();
+            app.set_app_menu(&app_menu);
+
+            let menu_bar: gio::Menu = builder.get_object("menu_bar").unwrap();
+            app.set_menubar(&menu_bar);
+
+            recent::set_menu(builder.get_object("recent_section").unwrap());
+            palette::set_menus(&[app_menu.upcast(), menu_bar.upcast()]);
+
+            {
+                let app = app.clone();
+                let wins = wins.clone();
+                gtk::timeout_add_seconds(2, move || {
+                    if prefs::reload() {
+                        apply_prefs(&app, &wins, &prefs::get());
+                    }
+                    Continue(true)
+                });
+            }
+
+            let wins = wins.clone();
+            gtk::timeout_add_seconds(recovery::SNAPSHOT_INTERVAL, move || {
+                if prefs::get().autosave {
+                    recovery::autosave_all(&wins);
+                }
+                recovery::snapshot_all(&wins);
+                Continue(true)
+            });
+        });
+    }
+
+    let recovery_offered = Rc::new(Cell::new(false));
+
+    {
+        let wins = wins.clone();
+        let recovery_offered = recovery_offered.clone();
+        app.connect_activate(move |app| {
+            if !recovery_offered.replace(true) {
+                let recovered = offer_recovery(app, &wins);
+                if session::restore(app, &wins) || recovered {
+                    return;
+                }
+            }
+            let w = Window::create(app, wins.clone());
+            w.init();
+        });
+    }
+
+    {
+        let wins = wins.clone();
+        let recovery_offered = recovery_offered.clone();
+        app.connect_open(move |app, files, _| {
+            if !recovery_offered.replace(true) {
+                offer_recovery(app, &wins);
+            }
+            // Files open already are shown in the window holding them, and a
+            // new window is only made for the others.
+            let mut new_win: Option<Window> = None;
+            for file in files {
+                let (file, pos) = split_location(file);
+                match wins.get_page(&file) {
+                    Some(p) => {
+                        let (i, _) = wins.locate(&p).unwrap();
+                        let owner = wins.borrow()[i].clone();
+                        owner.present(p);
+                    },
+                    None => {
+                        let w = new_win.get_or_insert_with(|| Window::create(app, wins.clone()));
+                        w.open(&file, wins.clone(), false);
+                    },
+                }
+                if let (Some(pos), Some(p)) = (pos, wins.get_page(&file)) {
+                    p.goto(pos);
+                }
+            }
+            if let Some(w) = new_win {
+                w.init();
+            }
+        });
+    }
+
+    wins
+}
+
+pub fn run(args: Args) {
+    resources::register();
+
+    match gtk::Application::new(APP_ID, gio::APPLICATION_HANDLES_OPEN) {
+        Ok(app) => {
+            build(&app);
+
+            let args: Vec<String> = args.collect();
+            let argv: Vec<&str> = args.iter().map(|s| s.as_ref()).collect();
+
+            app.run(argv.as_slice());
+        },
+
+        Err(_) => {
+            eprintln!("Application run error");
+        }
+    };
+}
+
+// "file:line[:column]" is only split off when the argument itself does not
+// name an existing file.
+fn split_location(file: &gio::File) -> (gio::File, Option<location::Position>) {
+    if file.query_exists(None) {
+        return (file.clone(), None);
+    }
+
+    if let Some(path) = file.get_path() {
+        if let Some((base, pos)) = path.to_str().and_then(location::split_location) {
+            return (gio::File::new_for_path(base), Some(pos));
+        }
+    } else if let Some(uri) = file.get_uri() {
+        if let Some((base, pos)) = location::split_location(&uri) {
+            return (gio::File::new_for_uri(base), Some(pos));
+        }
+    }
+    (file.clone(), None)
+}
@@ -0,0 +1,172 @@
+use std::cmp;
+
+// A rectangular selection between two corners given as (line, column).
+// Columns are display columns with tabs expanded, so the block stays
+// straight over lines indented differently, and may lie past the end of
+// short lines. A block with no width is a cursor spanning several lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub anchor: (usize, usize),
+    pub head: (usize, usize),
+}
+
+impl Block {
+    pub fn new(anchor: (usize, usize), head: (usize, usize)) -> Block {
+        Block { anchor, head }
+    }
+
+    pub fn lines(&self) -> (usize, usize) {
+        (cmp::min(self.anchor.0, self.head.0), cmp::max(self.anchor.0, self.head.0))
+    }
+
+    // Left inclusive, right exclusive.
+    pub fn columns(&self) -> (usize, usize) {
+        (cmp::min(self.anchor.1, self.head.1), cmp::max(self.anchor.1, self.head.1))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor.1 == self.head.1
+    }
+
+    // The same lines, collapsed to a cursor at `column`.
+    pub fn at_column(&self, column: usize) -> Block {
+        Block::new((self.anchor.0, column), (self.head.0, column))
+    }
+}
+
+fn advance(column: usize, c: char, tab_width: u32) -> usize {
+    let width = cmp::max(tab_width, 1) as usize;
+    if c == '\t' { column + width - column % width } else { column + 1 }
+}
+
+pub fn column_at(line: &str, index: usize, tab_width: u32) -> usize {
+    line.chars().take(index).fold(0, |col, c| advance(col, c, tab_width))
+}
+
+// The index of the first character starting at or after `column`, or the
+// line's length if it ends before.
+pub fn index_at(line: &str, column: usize, tab_width: u32) -> usize {
+    let mut col = 0;
+    for (i, c) in line.chars().enumerate() {
+        if col >= column {
+            return i;
+        }
+        col = advance(col, c, tab_width);
+    }
+    line.chars().count()
+}
+
+fn split(line: &str, left: usize, right: usize, tab_width: u32) -> (String, String, String) {
+    let start = index_at(line, left, tab_width);
+    let end = cmp::max(index_at(line, right, tab_width), start);
+    let before: String = line.chars().take(start).collect();
+    let inside: String = line.chars().skip(start).take(end - start).collect();
+    let after: String = line.chars().skip(end).collect();
+    (before, inside, after)
+}
+
+// The part of each line inside the columns.
+pub fn extract(lines: &[&str], left: usize, right: usize, tab_width: u32) -> Vec<String> {
+    lines.iter().map(|l| split(l, left, right, tab_width).1).collect()
+}
+
+// Replaces the columns of each line with the matching entry of `text`, or
+// with its only entry on every line. Lines too short to reach `left` are
+// padded with spaces, as are entries narrower than the widest one when
+// text follows them, and lines are added when `text` has more entries
+// than there are lines.
+pub fn replace(lines: &[&str], left: usize, right: usize, text: &[String], tab_width: u32) -> Vec<String> {
+    let count = cmp::max(lines.len(), text.len());
+    let fill = width(text, tab_width);
+    (0..count).map(|i| {
+        let line = lines.get(i).cloned().unwrap_or("");
+        let insert = match text.len() {
+            0 => "",
+            1 => text[0].as_str(),
+            _ => text.get(i).map_or("", |t| t.as_str()),
+        };
+        let (mut before, _, after) = split(line, left, right, tab_width);
+        let reached = column_at(&before, before.chars().count(), tab_width);
+        if !insert.is_empty() && reached < left {
+            before.push_str(&" ".repeat(left - reached));
+        }
+        let mut insert = insert.to_string();
+        let inserted = column_at(&insert, insert.chars().count(), tab_width);
+        if !after.is_empty() && inserted < fill {
+            insert.push_str(&" ".repeat(fill - inserted));
+        }
+        before + &insert + &after
+    }).collect()
+}
+
+// The display width of the widest entry, the width a pasted block takes.
+pub fn width(text: &[String], tab_width: u32) -> usize {
+    text.iter().map(|t| column_at(t, t.chars().count(), tab_width)).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(text: &[&str]) -> Vec<String> {
+        text.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn corners_in_any_order() {
+        let block = Block::new((3, 5), (1, 2));
+        assert_eq!(block.lines(), (1, 3));
+        assert_eq!(block.columns(), (2, 5));
+        assert!(!block.is_empty());
+        assert!(block.at_column(4).is_empty());
+    }
+
+    #[test]
+    fn columns_expand_tabs() {
+        assert_eq!(column_at("\tab", 1, 4), 4);
+        assert_eq!(column_at("\tab", 2, 4), 5);
+        assert_eq!(column_at("a\tb", 2, 4), 4);
+        assert_eq!(column_at("a\tb", 2, 0), 2);
+        assert_eq!(index_at("\tab", 0, 4), 0);
+        assert_eq!(index_at("\tab", 2, 4), 1);
+        assert_eq!(index_at("\tab", 5, 4), 2);
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        assert_eq!(column_at("日本語", 2, 4), 2);
+        assert_eq!(index_at("日本語", 2, 4), 2);
+        assert_eq!(extract(&["日本語です"], 1, 3, 4), strings(&["本語"]));
+    }
+
+    #[test]
+    fn short_lines_give_what_they_reach() {
+        assert_eq!(index_at("ab", 5, 4), 2);
+        assert_eq!(extract(&["abcd", "ab", ""], 1, 3, 4), strings(&["bc", "b", ""]));
+        assert_eq!(extract(&["\tabc"], 1, 3, 4), strings(&[""]));
+    }
+
+    #[test]
+    fn replace_pads_short_lines() {
+        let lines = replace(&["ab", "abcdef"], 4, 5, &strings(&["X"]), 4);
+        assert_eq!(lines, strings(&["ab  X", "abcdXf"]));
+    }
+
+    #[test]
+    fn replace_aligns_narrow_entries() {
+        let lines = replace(&["abc", "abc"], 1, 2, &strings(&["日本", "x"]), 4);
+        assert_eq!(lines, strings(&["a日本c", "ax c"]));
+    }
+
+    #[test]
+    fn replace_adds_lines_for_extra_entries() {
+        let lines = replace(&["ab"], 1, 1, &strings(&["x", "y"]), 4);
+        assert_eq!(lines, strings(&["axb", " y"]));
+    }
+
+    #[test]
+    fn replace_after_tab_and_delete() {
+        assert_eq!(replace(&["\tb"], 4, 4, &strings(&["x"]), 4), strings(&["\txb"]));
+        assert_eq!(replace(&["abc", "de"], 1, 2, &[], 4), strings(&["ac", "d"]));
+    }
+}
@@ -0,0 +1,151 @@
+// Pairs are given as two character strings such as "()" or "\"\"".
+
+pub fn default_brackets() -> Vec<String> {
+    vec!["()".to_string(), "[]".to_string(), "{}".to_string()]
+}
+
+pub fn default_auto_close() -> Vec<String> {
+    vec!["()".to_string(), "[]".to_string(), "{}".to_string(), "\"\"".to_string()]
+}
+
+fn ends(pair: &str) -> Option<(char, char)> {
+    let mut chars = pair.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(open), Some(close), None) => Some((open, close)),
+        _ => None,
+    }
+}
+
+// The partner of the bracket `c` and whether it comes after it.
+pub fn partner(c: char, pairs: &[String]) -> Option<(char, bool)> {
+    pairs.iter().filter_map(|p| ends(p)).filter(|&(open, close)| open != close).find_map(|(open, close)| {
+        if c == open {
+            Some((close, true))
+        } else if c == close {
+            Some((open, false))
+        } else {
+            None
+        }
+    })
+}
+
+// Follows `chars`, which run away from `bracket` starting next to it, and
+// returns the position paired with its partner, counting brackets nested
+// in between. Stops as soon as the partner is found.
+pub fn find_partner<T, I: Iterator<Item = (char, T)>>(chars: I, bracket: char, partner: char) -> Option<T> {
+    let mut depth = 0;
+    for (c, position) in chars {
+        if c == partner {
+            if depth == 0 {
+                return Some(position);
+            }
+            depth -= 1;
+        } else if c == bracket {
+            depth += 1;
+        }
+    }
+    None
+}
+
+// The character to insert after `typed`, if any, given the characters
+// before and after the cursor. Pairs are only closed in front of space or
+// another closing character, and quotes not right after a word, so that
+// apostrophes stay single.
+pub fn closing(typed: char, before: Option<char>, after: Option<char>, pairs: &[String]) -> Option<char> {
+    let (open, close) = pairs.iter().filter_map(|p| ends(p)).find(|&(open, _)| open == typed)?;
+    let closes = pairs.iter().filter_map(|p| ends(p)).any(|(_, c)| Some(c) == after);
+    if !(after.is_none_or(char::is_whitespace) || closes) {
+        return None;
+    }
+    if open == close && before.is_some_and(|c| c.is_alphanumeric() || c == open) {
+        return None;
+    }
+    Some(close)
+}
+
+// The closing character of the pair `typed` opens, for wrapping a selection.
+pub fn wrapping(typed: char, pairs: &[String]) -> Option<char> {
+    pairs.iter().filter_map(|p| ends(p)).find(|&(open, _)| open == typed).map(|(_, close)| close)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The index in `text` of the partner of the bracket at `at`.
+    fn partner_index(text: &str, at: usize, skip: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let chars: Vec<char> = text.chars().collect();
+        let (partner, forward) = partner(chars[at], &default_brackets())?;
+        let indices: Vec<usize> = if forward { (at + 1..chars.len()).collect() } else { (0..at).rev().collect() };
+        find_partner(indices.into_iter().filter(|&i| !skip(i)).map(|i| (chars[i], i)), chars[at], partner)
+    }
+
+    fn find(text: &str, at: usize) -> Option<usize> {
+        partner_index(text, at, &|_| false)
+    }
+
+    #[test]
+    fn partners_of_brackets() {
+        let pairs = default_brackets();
+        assert_eq!(partner('(', &pairs), Some((')', true)));
+        assert_eq!(partner('}', &pairs), Some(('{', false)));
+        assert_eq!(partner('a', &pairs), None);
+        assert_eq!(partner('"', &default_auto_close()), None);
+    }
+
+    #[test]
+    fn finds_forward_past_nested_brackets() {
+        assert_eq!(find("(a(b)c)d", 0), Some(6));
+        assert_eq!(find("(a(b)c)d", 2), Some(4));
+        assert_eq!(find("[(])", 0), Some(2));
+    }
+
+    #[test]
+    fn finds_backward_past_nested_brackets() {
+        assert_eq!(find("a{b{c}d}", 7), Some(1));
+        assert_eq!(find("a{b{c}d}", 5), Some(3));
+    }
+
+    #[test]
+    fn unbalanced_brackets_have_no_partner() {
+        assert_eq!(find("(a(b)c", 0), None);
+        assert_eq!(find("a)b)", 3), None);
+        assert_eq!(find("(a))", 0), Some(2));
+    }
+
+    #[test]
+    fn skips_characters_in_literals() {
+        let text = "(a \")\" b)";
+        let in_string = |i: usize| (3..=5).contains(&i);
+        assert_eq!(find(text, 0), Some(4));
+        assert_eq!(partner_index(text, 0, &in_string), Some(8));
+    }
+
+    #[test]
+    fn closes_before_space_or_closer() {
+        let pairs = default_auto_close();
+        assert_eq!(closing('(', Some('a'), None, &pairs), Some(')'));
+        assert_eq!(closing('[', None, Some(' '), &pairs), Some(']'));
+        assert_eq!(closing('{', None, Some(')'), &pairs), Some('}'));
+        assert_eq!(closing('(', None, Some('a'), &pairs), None);
+        assert_eq!(closing('x', None, None, &pairs), None);
+    }
+
+    #[test]
+    fn closes_quotes_outside_words() {
+        let pairs = default_auto_close();
+        assert_eq!(closing('"', Some(' '), None, &pairs), Some('"'));
+        assert_eq!(closing('"', None, Some(')'), &pairs), Some('"'));
+        assert_eq!(closing('"', Some('a'), None, &pairs), None);
+        assert_eq!(closing('"', Some('"'), None, &pairs), None);
+        assert_eq!(closing('"', Some(' '), Some('a'), &pairs), None);
+    }
+
+    #[test]
+    fn wraps_with_closing_character() {
+        let pairs = default_auto_close();
+        assert_eq!(wrapping('[', &pairs), Some(']'));
+        assert_eq!(wrapping('"', &pairs), Some('"'));
+        assert_eq!(wrapping(')', &pairs), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::cmp;
+
+// Carets are given as character offsets (start, end) of their selection,
+// equal when nothing is selected, the cursor's own first.
+
+// The indices of carets to drop because they share a position with, or
+// overlap the selection of, a caret before them. The first is always kept.
+pub fn overlapping(carets: &[(usize, usize)]) -> Vec<usize> {
+    let mut kept: Vec<(usize, usize)> = Vec::new();
+    let mut dropped = Vec::new();
+    for (i, &(start, end)) in carets.iter().enumerate() {
+        if kept.iter().any(|&(s, e)| start == s || (start < e && s < end)) {
+            dropped.push(i);
+        } else {
+            kept.push((start, end));
+        }
+    }
+    dropped
+}
+
+// The start of the first occurrence of `needle` at or after `from`,
+// wrapping around the end of `text`, that no caret has selected yet.
+pub fn next_occurrence(text: &str, needle: &str, from: usize, carets: &[(usize, usize)]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    let len = needle.chars().count();
+
+    let (mut byte, mut chars) = (0, 0);
+    let mut starts = Vec::new();
+    for (i, _) in text.match_indices(needle) {
+        chars += text[byte..i].chars().count();
+        byte = i;
+        starts.push(chars);
+    }
+
+    let free = |start: &usize| !carets.iter().any(|&(s, e)| *start < cmp::max(e, s + 1) && s < *start + len);
+    starts.iter().cloned().filter(|&s| s >= from)
+        .chain(starts.iter().cloned().filter(|&s| s < from))
+        .find(free)
+}
+
+// What to paste at each of `count` carets: a line each when the text has
+// one line per caret, otherwise all of it everywhere.
+pub fn paste_text(text: &str, count: usize) -> Vec<String> {
+    let lines: Vec<&str> = text.trim_end_matches('\n').split('\n').collect();
+    if count > 1 && lines.len() == count {
+        lines.iter().map(|l| l.trim_end_matches('\r').to_string()).collect()
+    } else {
+        vec![text.to_string(); count]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_carets_at_same_position() {
+        assert_eq!(overlapping(&[(0, 0), (4, 4), (0, 0), (4, 4)]), vec![2, 3]);
+    }
+
+    #[test]
+    fn drops_overlapping_selections() {
+        assert_eq!(overlapping(&[(0, 4), (2, 6)]), vec![1]);
+        assert_eq!(overlapping(&[(5, 8), (6, 6), (8, 8), (2, 3)]), vec![1]);
+        assert_eq!(overlapping(&[(2, 6), (0, 4)]), vec![1]);
+    }
+
+    #[test]
+    fn finds_next_free_occurrence() {
+        let text = "foo bar foo baz foo";
+        assert_eq!(next_occurrence(text, "foo", 3, &[(0, 3)]), Some(8));
+        assert_eq!(next_occurrence(text, "foo", 11, &[(0, 3), (8, 11)]), Some(16));
+        assert_eq!(next_occurrence(text, "bar", 0, &[]), Some(4));
+    }
+
+    #[test]
+    fn next_occurrence_wraps_around() {
+        let text = "foo bar foo baz foo";
+        assert_eq!(next_occurrence(text, "foo", 19, &[(8, 11), (16, 19)]), Some(0));
+        assert_eq!(next_occurrence(text, "foo", 19, &[(0, 3), (8, 11), (16, 19)]), None);
+        assert_eq!(next_occurrence(text, "", 0, &[]), None);
+    }
+
+    #[test]
+    fn next_occurrence_counts_characters() {
+        assert_eq!(next_occurrence("日本 日本", "日本", 2, &[(0, 2)]), Some(3));
+    }
+
+    #[test]
+    fn pastes_a_line_per_caret() {
+        assert_eq!(paste_text("a\nb\n", 2), vec!["a", "b"]);
+        assert_eq!(paste_text("a\r\nb\r\n", 2), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn pastes_everything_when_counts_differ() {
+        assert_eq!(paste_text("a\nb", 3), vec!["a\nb", "a\nb", "a\nb"]);
+        assert_eq!(paste_text("a\nb", 1), vec!["a\nb"]);
+        assert_eq!(paste_text("x", 2), vec!["x", "x"]);
+    }
+}
@@ -0,0 +1,560 @@
+extern crate encoding_rs;
+extern crate chrono;
+
+use encoding_rs::{Encoding, UTF_8};
+use chrono::Local;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use encoding;
+use eol::{self, LineEnding};
+use undo::UndoStack;
+
+// What a page should do after its file was looked at on disk again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskChange {
+    Unchanged,
+    Deleted,
+    Restored,
+    Reload,
+    Conflict,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseResponse {
+    Save,
+    Discard,
+    Cancel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseAction {
+    Close,
+    Keep,
+    Save,
+    SaveAs,
+}
+
+// Where a document's file is kept. Etags identify versions of its contents
+// so that changes made by others are noticed.
+pub trait Storage {
+    fn uri(&self) -> Option<String>;
+    // The local path, for timestamped backups.
+    fn path(&self) -> Option<PathBuf>;
+    fn load(&self) -> Result<(Vec<u8>, Option<String>), String>;
+    // The etag of the contents kept now, None if there are none.
+    fn current_etag(&self) -> Option<String>;
+    // Replaces the contents unless they have changed since `etag`, keeping
+    // the old ones aside when `keep_old` is set. Returns the new etag.
+    fn store(&self, bytes: &[u8], etag: Option<&str>, keep_old: bool) -> Result<String, String>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveError {
+    Encode(String),
+    Backup(String),
+    // The file was changed by someone else since it was loaded or saved.
+    Conflict,
+    Write(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Encode(ref msg) => write!(f, "{}", msg),
+            SaveError::Backup(ref e) => write!(f, "Error: Cannot create backup: {}", e),
+            SaveError::Conflict => write!(f, "The file has been changed on disk since it was opened."),
+            SaveError::Write(ref e) => write!(f, "Error: Cannot save file: {}", e),
+        }
+    }
+}
+
+// The state of an open file apart from its text, which lives in the view's
+// buffer. Etags are those of the file when it was last loaded or saved
+// (`etag`) and when it was last looked at on disk (`disk_etag`).
+pub struct Document {
+    uri: Option<String>,
+    etag: Option<String>,
+    disk_etag: Option<String>,
+    modified: bool,
+    encoding: &'static Encoding,
+    bom: bool,
+    line_ending: LineEnding,
+    pub undo: UndoStack,
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document {
+            uri: None,
+            etag: None,
+            disk_etag: None,
+            modified: false,
+            encoding: UTF_8,
+            bom: false,
+            line_ending: LineEnding::default(),
+            undo: UndoStack::new(),
+        }
+    }
+
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_deref()
+    }
+
+    // Saving to another file must not be refused because of the old one's etag.
+    pub fn set_uri(&mut self, uri: Option<String>) {
+        if uri != self.uri {
+            self.etag = None;
+        }
+        self.uri = uri;
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    pub fn disk_etag(&self) -> Option<&str> {
+        self.disk_etag.as_deref()
+    }
+
+    pub fn set_disk_etag(&mut self, etag: Option<String>) {
+        self.disk_etag = etag;
+    }
+
+    pub fn modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn set_modified(&mut self, modified: bool) {
+        self.modified = modified;
+    }
+
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    // A byte order mark is only kept while saving in the encoding it came with.
+    pub fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if encoding != self.encoding {
+            self.bom = false;
+        }
+        self.encoding = encoding;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.modified = true;
+        }
+    }
+
+    // Takes the raw contents of `uri` and returns the text for the buffer,
+    // with line endings normalized to "\n".
+    pub fn load(&mut self, uri: &str, bytes: &[u8], etag: Option<String>,
+                encoding: Option<&'static Encoding>) -> String {
+        let (text, enc, bom) = encoding::decode(bytes, encoding);
+        self.uri = Some(uri.to_string());
+        self.encoding = enc;
+        self.bom = bom;
+        self.line_ending = eol::detect(&text);
+        self.etag = etag.clone();
+        self.disk_etag = etag;
+        self.modified = false;
+        self.undo.clear();
+        self.undo.mark_saved();
+        eol::normalize(&text)
+    }
+
+    // The bytes to write for the buffer's `text`.
+    pub fn contents(&self, text: &str) -> Result<Vec<u8>, String> {
+        let text = eol::apply(text, self.line_ending);
+        encoding::encode(&text, self.encoding, self.bom).ok_or_else(|| {
+            format!("Error: Cannot encode text as {}", self.encoding.name())
+        })
+    }
+
+    // Reads the file from `storage` and returns the text for the buffer.
+    pub fn open<S: Storage>(&mut self, storage: &S, encoding: Option<&'static Encoding>) -> Result<String, String> {
+        let uri = storage.uri().ok_or_else(|| "Error: The file has no location".to_string())?;
+        let (bytes, etag) = storage.load()?;
+        Ok(self.load(&uri, &bytes, etag, encoding))
+    }
+
+    // Writes the buffer's `text` to `storage`, first copying the old file
+    // into `backup_dir` if given. Fails with a conflict if the file was
+    // changed by someone else, unless told to `overwrite` it.
+    pub fn save<S: Storage>(&mut self, storage: &S, text: &str, keep_old: bool,
+                            backup_dir: Option<&Path>, overwrite: bool) -> Result<(), SaveError> {
+        let bytes = self.contents(text).map_err(SaveError::Encode)?;
+        if let (Some(dir), Some(path)) = (backup_dir, storage.path()) {
+            backup_copy(&path, dir).map_err(|e| SaveError::Backup(e.to_string()))?;
+        }
+
+        let etag = if overwrite { None } else { self.etag.clone() };
+        match storage.store(&bytes, etag.as_deref(), keep_old) {
+            Ok(new_etag) => {
+                self.saved(Some(new_etag));
+                Ok(())
+            },
+            Err(_) if etag.is_some() && storage.current_etag() != etag => Err(SaveError::Conflict),
+            Err(e) => Err(SaveError::Write(e)),
+        }
+    }
+
+    pub fn saved(&mut self, etag: Option<String>) {
+        self.etag = etag.clone();
+        self.disk_etag = etag;
+        self.modified = false;
+        self.undo.mark_saved();
+    }
+
+    // After an undo or redo the text is unmodified exactly when it is back
+    // at the last save.
+    pub fn update_modified(&mut self) {
+        self.modified = !self.undo.is_saved();
+    }
+
+    // Compares the file's current etag on disk (None if it is gone) with the
+    // last one seen.
+    pub fn check_disk(&mut self, disk_etag: Option<String>) -> DiskChange {
+        if self.uri.is_none() || disk_etag == self.disk_etag {
+            return DiskChange::Unchanged;
+        }
+        self.disk_etag = disk_etag;
+
+        if self.disk_etag.is_none() {
+            self.modified = true;
+            DiskChange::Deleted
+        } else if self.disk_etag == self.etag {
+            DiskChange::Restored
+        } else if !self.modified {
+            DiskChange::Reload
+        } else {
+            DiskChange::Conflict
+        }
+    }
+
+    pub fn needs_close_confirm(&self) -> bool {
+        self.modified
+    }
+
+    // `response` is None when the confirmation was dismissed without an answer.
+    pub fn close_action(&self, response: Option<CloseResponse>) -> CloseAction {
+        if !self.modified {
+            return CloseAction::Close;
+        }
+        match response {
+            Some(CloseResponse::Save) if self.uri.is_some() => CloseAction::Save,
+            Some(CloseResponse::Save) => CloseAction::SaveAs,
+            Some(CloseResponse::Discard) => CloseAction::Close,
+            Some(CloseResponse::Cancel) | None => CloseAction::Keep,
+        }
+    }
+}
+
+// Copies `path` into `dir` with a timestamp appended to its name.
+pub fn backup_copy(path: &Path, dir: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    fs::create_dir_all(dir)?;
+    fs::copy(path, dir.join(format!("{}.{}", name, stamp)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, UTF_16LE};
+    use std::env;
+    use std::fs;
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+    use std::process;
+    use undo::Edit;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("vanilla_text-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn round_trip(bytes: &[u8], encoding: Option<&'static Encoding>) -> (Document, String, Vec<u8>) {
+        let mut doc = Document::new();
+        let text = doc.load("file:///tmp/a.txt", bytes, None, encoding);
+        let saved = doc.contents(&text).unwrap();
+        (doc, text, saved)
+    }
+
+    // A file kept in memory whose etag counts the times it was stored.
+    struct Memory {
+        contents: RefCell<Option<(Vec<u8>, u32)>>,
+        old: RefCell<Option<Vec<u8>>>,
+        read_only: bool,
+    }
+
+    impl Memory {
+        fn new(bytes: &[u8]) -> Memory {
+            Memory { contents: RefCell::new(Some((bytes.to_vec(), 1))), old: RefCell::new(None), read_only: false }
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            self.contents.borrow().as_ref().unwrap().0.clone()
+        }
+    }
+
+    impl Storage for Memory {
+        fn uri(&self) -> Option<String> {
+            Some("file:///memory.txt".to_string())
+        }
+
+        fn path(&self) -> Option<PathBuf> {
+            None
+        }
+
+        fn load(&self) -> Result<(Vec<u8>, Option<String>), String> {
+            Ok((self.bytes(), self.current_etag()))
+        }
+
+        fn current_etag(&self) -> Option<String> {
+            self.contents.borrow().as_ref().map(|&(_, n)| n.to_string())
+        }
+
+        fn store(&self, bytes: &[u8], etag: Option<&str>, keep_old: bool) -> Result<String, String> {
+            if self.read_only {
+                return Err("read-only".to_string());
+            }
+            if etag.is_some() && etag.map(|e| e.to_string()) != self.current_etag() {
+                return Err("wrong etag".to_string());
+            }
+            let n = self.contents.borrow().as_ref().map_or(1, |&(_, n)| n + 1);
+            if keep_old {
+                *self.old.borrow_mut() = self.contents.borrow().as_ref().map(|c| c.0.clone());
+            }
+            *self.contents.borrow_mut() = Some((bytes.to_vec(), n));
+            Ok(n.to_string())
+        }
+    }
+
+    #[test]
+    fn utf8_round_trip() {
+        let (doc, text, saved) = round_trip("hello\nwörld\n".as_bytes(), None);
+        assert_eq!(text, "hello\nwörld\n");
+        assert_eq!(doc.encoding(), UTF_8);
+        assert_eq!(saved, "hello\nwörld\n".as_bytes());
+    }
+
+    #[test]
+    fn crlf_round_trip() {
+        let (doc, text, saved) = round_trip(b"a\r\nb\r\n", None);
+        assert_eq!(text, "a\nb\n");
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        assert_eq!(saved, b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn bom_round_trip() {
+        let (_, text, saved) = round_trip(b"\xef\xbb\xbfbom\n", None);
+        assert_eq!(text, "bom\n");
+        assert_eq!(saved, b"\xef\xbb\xbfbom\n");
+    }
+
+    #[test]
+    fn utf16_round_trip() {
+        let bytes = b"\xff\xfeh\x00i\x00\n\x00";
+        let (doc, text, saved) = round_trip(bytes, None);
+        assert_eq!(doc.encoding(), UTF_16LE);
+        assert_eq!(text, "hi\n");
+        assert_eq!(saved, bytes);
+    }
+
+    #[test]
+    fn shift_jis_round_trip() {
+        let (bytes, _, _) = SHIFT_JIS.encode("日本語\n");
+        let (doc, text, saved) = round_trip(&bytes, Some(SHIFT_JIS));
+        assert_eq!(doc.encoding(), SHIFT_JIS);
+        assert_eq!(text, "日本語\n");
+        assert_eq!(saved, &bytes[..]);
+    }
+
+    #[test]
+    fn unencodable_text_is_an_error() {
+        let mut doc = Document::new();
+        doc.load("file:///tmp/a.txt", b"abc", None, Some(SHIFT_JIS));
+        assert!(doc.contents("\u{1f600}").is_err());
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = temp_path("round-trip.txt");
+        fs::write(&path, b"one\r\ntwo\r\n").unwrap();
+
+        let mut doc = Document::new();
+        let text = doc.load("file:///round-trip.txt", &fs::read(&path).unwrap(), None, None);
+        fs::write(&path, doc.contents(&format!("{}three\n", text)).unwrap()).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"one\r\ntwo\r\nthree\r\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn backup_copy_keeps_the_old_contents() {
+        let path = temp_path("backup.txt");
+        let dir = temp_path("backups");
+        fs::write(&path, b"old").unwrap();
+
+        backup_copy(&path, &dir).unwrap();
+        let copies: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(fs::read(&copies[0]).unwrap(), b"old");
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn line_ending_change_modifies() {
+        let mut doc = Document::new();
+        doc.set_line_ending(LineEnding::default());
+        assert!(!doc.modified());
+        doc.set_line_ending(LineEnding::CrLf);
+        assert!(doc.modified());
+    }
+
+    #[test]
+    fn undo_to_save_point_is_unmodified() {
+        let mut doc = Document::new();
+        doc.load("file:///a", b"a", Some("1".to_string()), None);
+        doc.undo.record(Edit::Insert { offset: 1, text: "b".to_string() });
+        doc.set_modified(true);
+        doc.undo.undo();
+        doc.update_modified();
+        assert!(!doc.modified());
+        doc.undo.redo();
+        doc.update_modified();
+        assert!(doc.modified());
+    }
+
+    #[test]
+    fn new_uri_drops_etag() {
+        let mut doc = Document::new();
+        doc.load("file:///a", b"", Some("1".to_string()), None);
+        doc.set_uri(Some("file:///a".to_string()));
+        assert_eq!(doc.etag(), Some("1"));
+        doc.set_uri(Some("file:///b".to_string()));
+        assert_eq!(doc.etag(), None);
+    }
+
+    #[test]
+    fn disk_changes() {
+        let mut doc = Document::new();
+        doc.load("file:///a", b"", Some("1".to_string()), None);
+        assert_eq!(doc.check_disk(Some("1".to_string())), DiskChange::Unchanged);
+        assert_eq!(doc.check_disk(Some("2".to_string())), DiskChange::Reload);
+
+        doc.saved(Some("3".to_string()));
+        doc.set_modified(true);
+        assert_eq!(doc.check_disk(Some("4".to_string())), DiskChange::Conflict);
+        assert_eq!(doc.check_disk(Some("3".to_string())), DiskChange::Restored);
+        assert_eq!(doc.check_disk(None), DiskChange::Deleted);
+        assert!(doc.modified());
+    }
+
+    #[test]
+    fn unmodified_closes_without_asking() {
+        let doc = Document::new();
+        assert!(!doc.needs_close_confirm());
+        assert_eq!(doc.close_action(None), CloseAction::Close);
+        assert_eq!(doc.close_action(Some(CloseResponse::Save)), CloseAction::Close);
+    }
+
+    #[test]
+    fn modified_close_follows_response() {
+        let mut doc = Document::new();
+        doc.set_modified(true);
+        assert!(doc.needs_close_confirm());
+        assert_eq!(doc.close_action(Some(CloseResponse::Save)), CloseAction::SaveAs);
+        assert_eq!(doc.close_action(Some(CloseResponse::Discard)), CloseAction::Close);
+        assert_eq!(doc.close_action(Some(CloseResponse::Cancel)), CloseAction::Keep);
+        assert_eq!(doc.close_action(None), CloseAction::Keep);
+
+        doc.load("file:///a", b"", None, None);
+        doc.set_modified(true);
+        assert_eq!(doc.close_action(Some(CloseResponse::Save)), CloseAction::Save);
+    }
+
+    #[test]
+    fn open_reads_storage() {
+        let storage = Memory::new(b"a\r\nb\r\n");
+        let mut doc = Document::new();
+        assert_eq!(doc.open(&storage, None), Ok("a\nb\n".to_string()));
+        assert_eq!(doc.uri(), Some("file:///memory.txt"));
+        assert_eq!(doc.etag(), Some("1"));
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+    }
+
+    #[test]
+    fn save_writes_encoded_text() {
+        let storage = Memory::new(b"a\r\n");
+        let mut doc = Document::new();
+        doc.open(&storage, None).unwrap();
+        doc.set_modified(true);
+
+        assert_eq!(doc.save(&storage, "a\nb\n", true, None, false), Ok(()));
+        assert_eq!(storage.bytes(), b"a\r\nb\r\n");
+        assert_eq!(*storage.old.borrow(), Some(b"a\r\n".to_vec()));
+        assert_eq!(doc.etag(), Some("2"));
+        assert!(!doc.modified());
+
+        assert_eq!(doc.save(&storage, "c\n", false, None, false), Ok(()));
+        assert_eq!(storage.bytes(), b"c\r\n");
+    }
+
+    #[test]
+    fn save_refuses_file_changed_elsewhere() {
+        let storage = Memory::new(b"a\n");
+        let mut doc = Document::new();
+        doc.open(&storage, None).unwrap();
+        storage.store(b"theirs\n", None, false).unwrap();
+
+        assert_eq!(doc.save(&storage, "mine\n", false, None, false), Err(SaveError::Conflict));
+        assert_eq!(storage.bytes(), b"theirs\n");
+        assert_eq!(doc.save(&storage, "mine\n", false, None, true), Ok(()));
+        assert_eq!(storage.bytes(), b"mine\n");
+        assert_eq!(doc.etag(), Some("3"));
+    }
+
+    #[test]
+    fn save_reports_failures() {
+        let mut storage = Memory::new(b"a\n");
+        let mut doc = Document::new();
+        doc.open(&storage, Some(SHIFT_JIS)).unwrap();
+        doc.set_modified(true);
+        match doc.save(&storage, "\u{1f600}", false, None, false) {
+            Err(SaveError::Encode(_)) => {},
+            other => panic!("expected an encoding error, got {:?}", other),
+        }
+
+        storage.read_only = true;
+        assert_eq!(doc.save(&storage, "b\n", false, None, false), Err(SaveError::Write("read-only".to_string())));
+        assert!(doc.modified());
+        assert_eq!(storage.bytes(), b"a\n");
+    }
+}
@@ -0,0 +1,200 @@
+extern crate encoding_rs;
+
+use encoding_rs::{
+    Encoding, UTF_8, UTF_16LE, UTF_16BE,
+    SHIFT_JIS, EUC_JP, ISO_2022_JP, EUC_KR, GBK, BIG5,
+    WINDOWS_1252, ISO_8859_2, ISO_8859_15, KOI8_R, WINDOWS_1251
+};
+
+pub fn encodings() -> Vec<&'static Encoding> {
+    vec![UTF_8, UTF_16LE, UTF_16BE,
+         SHIFT_JIS, EUC_JP, ISO_2022_JP,
+         EUC_KR, GBK, BIG5,
+         WINDOWS_1252, ISO_8859_2, ISO_8859_15,
+         WINDOWS_1251, KOI8_R]
+}
+
+pub fn for_name(name: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(name.as_bytes())
+}
+
+// Candidates tried, in order of preference, when the bytes carry no BOM and
+// are not valid UTF-8. windows-1252 maps every byte, so it always succeeds.
+fn fallback_candidates() -> Vec<&'static Encoding> {
+    vec![SHIFT_JIS, EUC_JP, WINDOWS_1252]
+}
+
+pub fn detect(bytes: &[u8]) -> (&'static Encoding, bool) {
+    if let Some((enc, _)) = Encoding::for_bom(bytes) {
+        return (enc, true);
+    }
+
+    // UTF-16 without a BOM is checked first, since ASCII text in it is also
+    // valid UTF-8, only full of NULs.
+    if let Some(enc) = detect_utf16(bytes) {
+        return (enc, false);
+    }
+
+    if ::std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, false);
+    }
+
+    let mut best = (WINDOWS_1252, usize::MAX);
+    for enc in fallback_candidates() {
+        if let Some(text) = enc.decode_without_bom_handling_and_without_replacement(bytes) {
+            let penalty = text.chars().filter(|c| is_unlikely(*c)).count();
+            if penalty < best.1 {
+                best = (enc, penalty);
+            }
+        }
+    }
+
+    (best.0, false)
+}
+
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let even = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    let half = bytes.len() / 2;
+
+    if odd * 2 > half && even == 0 {
+        Some(UTF_16LE)
+    } else if even * 2 > half && odd == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+fn is_unlikely(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        '\u{ff61}'..='\u{ff9f}' => true,
+        '\u{e000}'..='\u{f8ff}' => true,
+        c => c.is_control(),
+    }
+}
+
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, &'static Encoding, bool) {
+    let (encoding, bom) = match encoding {
+        Some(enc) => {
+            let bom = match Encoding::for_bom(bytes) {
+                Some((e, _)) => e == enc,
+                None => false,
+            };
+            (enc, bom)
+        },
+        None => detect(bytes),
+    };
+
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+
+    (text.into_owned(), encoding, bom)
+}
+
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    // encoding_rs follows the Encoding Standard, which writes UTF-8 when
+    // asked for UTF-16, so UTF-16 is encoded here.
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let le = encoding == UTF_16LE;
+        if bom {
+            bytes.extend_from_slice(if le { &[0xff, 0xfe] } else { &[0xfe, 0xff] });
+        }
+        for u in text.encode_utf16() {
+            bytes.extend_from_slice(&if le { u.to_le_bytes() } else { u.to_be_bytes() });
+        }
+        return Some(bytes);
+    }
+
+    if encoding == UTF_8 {
+        if bom {
+            bytes.extend_from_slice(&[0xef, 0xbb, 0xbf]);
+        }
+        bytes.extend_from_slice(text.as_bytes());
+        return Some(bytes);
+    }
+
+    let (encoded, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return None;
+    }
+    bytes.extend_from_slice(&encoded);
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, le: bool) -> Vec<u8> {
+        encode(text, if le { UTF_16LE } else { UTF_16BE }, false).unwrap()
+    }
+
+    #[test]
+    fn detects_bom() {
+        assert_eq!(detect(b"\xef\xbb\xbfa"), (UTF_8, true));
+        assert_eq!(detect(b"\xff\xfea\x00"), (UTF_16LE, true));
+        assert_eq!(detect(b"\xfe\xff\x00a"), (UTF_16BE, true));
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        assert_eq!(detect(&utf16("hello\nworld\n", true)), (UTF_16LE, false));
+        assert_eq!(detect(&utf16("hello\nworld\n", false)), (UTF_16BE, false));
+        assert_eq!(detect(&utf16("日本語 text\n", true)), (UTF_16LE, false));
+    }
+
+    #[test]
+    fn detects_utf8() {
+        assert_eq!(detect(b""), (UTF_8, false));
+        assert_eq!(detect(b"plain ascii\n"), (UTF_8, false));
+        assert_eq!(detect("wörld 日本\n".as_bytes()), (UTF_8, false));
+    }
+
+    #[test]
+    fn falls_back_to_legacy_encodings() {
+        let text = "日本語のテキストです。\n";
+        assert_eq!(detect(&SHIFT_JIS.encode(text).0), (SHIFT_JIS, false));
+        assert_eq!(detect(&EUC_JP.encode(text).0), (EUC_JP, false));
+        assert_eq!(detect(&WINDOWS_1252.encode("café naïve\n").0), (WINDOWS_1252, false));
+    }
+
+    #[test]
+    fn decode_removes_bom() {
+        assert_eq!(decode(b"\xef\xbb\xbfa\n", None), ("a\n".to_string(), UTF_8, true));
+        assert_eq!(decode(b"\xff\xfea\x00", Some(UTF_16LE)), ("a".to_string(), UTF_16LE, true));
+        assert_eq!(decode(b"a\x00", Some(UTF_16LE)), ("a".to_string(), UTF_16LE, false));
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = "Text 日本語\r\n";
+        for &enc in &[UTF_8, UTF_16LE, UTF_16BE, SHIFT_JIS, EUC_JP] {
+            for &bom in &[false, true] {
+                let bom = bom && (enc == UTF_8 || enc == UTF_16LE || enc == UTF_16BE);
+                let bytes = encode(text, enc, bom).unwrap();
+                assert_eq!(decode(&bytes, Some(enc)), (text.to_string(), enc, bom), "{}", enc.name());
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_utf16_byte_order() {
+        assert_eq!(encode("a", UTF_16LE, true), Some(vec![0xff, 0xfe, 0x61, 0x00]));
+        assert_eq!(encode("a", UTF_16BE, true), Some(vec![0xfe, 0xff, 0x00, 0x61]));
+        assert_eq!(encode("\u{1f600}", UTF_16LE, false), Some(vec![0x3d, 0xd8, 0x00, 0xde]));
+    }
+
+    #[test]
+    fn unencodable_text_fails() {
+        assert_eq!(encode("日本", WINDOWS_1252, false), None);
+        assert_eq!(encode("café", WINDOWS_1252, false), Some(vec![0x63, 0x61, 0x66, 0xe9]));
+    }
+}
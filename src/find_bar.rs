@@ -0,0 +1,171 @@
+extern crate gtk;
+
+use gtk::{
+    WidgetExt, EntryExt, ButtonExt, ToggleButtonExt,
+    RevealerExt, SearchEntryExt, LabelExt, StyleContextExt
+};
+
+use page::{Page, PageExtend};
+use search::Query;
+
+pub struct FindBar {
+    revealer: gtk::Revealer,
+    entry: gtk::SearchEntry,
+    replace_entry: gtk::Entry,
+    replace_box: gtk::Box,
+    prev_button: gtk::Button,
+    next_button: gtk::Button,
+    case_button: gtk::ToggleButton,
+    word_button: gtk::ToggleButton,
+    regex_button: gtk::ToggleButton,
+    status: gtk::Label,
+    close_button: gtk::Button,
+    replace_button: gtk::Button,
+    replace_all_button: gtk::Button,
+}
+
+impl FindBar {
+    pub fn new(builder: &gtk::Builder) -> FindBar {
+        FindBar {
+            revealer: builder.get_object("find_revealer").unwrap(),
+            entry: builder.get_object("find_entry").unwrap(),
+            replace_entry: builder.get_object("replace_entry").unwrap(),
+            replace_box: builder.get_object("replace_box").unwrap(),
+            prev_button: builder.get_object("find_prev_button").unwrap(),
+            next_button: builder.get_object("find_next_button").unwrap(),
+            case_button: builder.get_object("case_button").unwrap(),
+            word_button: builder.get_object("word_button").unwrap(),
+            regex_button: builder.get_object("regex_button").unwrap(),
+            status: builder.get_object("find_status").unwrap(),
+            close_button: builder.get_object("find_close_button").unwrap(),
+            replace_button: builder.get_object("replace_button").unwrap(),
+            replace_all_button: builder.get_object("replace_all_button").unwrap(),
+        }
+    }
+
+    pub fn connect(&self, page: &Page) {
+        {
+            let p = page.clone();
+            self.entry.connect_search_changed(move |_| {
+                p.update_matches();
+            });
+        }
+
+        for button in &[&self.case_button, &self.word_button, &self.regex_button] {
+            let p = page.clone();
+            button.connect_toggled(move |_| {
+                p.update_matches();
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.entry.connect_activate(move |_| {
+                p.find_next(true);
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.entry.connect_next_match(move |_| {
+                p.find_next(true);
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.entry.connect_previous_match(move |_| {
+                p.find_next(false);
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.next_button.connect_clicked(move |_| {
+                p.find_next(true);
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.prev_button.connect_clicked(move |_| {
+                p.find_next(false);
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.entry.connect_stop_search(move |_| {
+                p.hide_find_bar();
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.close_button.connect_clicked(move |_| {
+                p.hide_find_bar();
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.replace_entry.connect_activate(move |_| {
+                p.replace_match();
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.replace_button.connect_clicked(move |_| {
+                p.replace_match();
+            });
+        }
+
+        {
+            let p = page.clone();
+            self.replace_all_button.connect_clicked(move |_| {
+                p.replace_all_matches();
+            });
+        }
+    }
+
+    pub fn query(&self) -> Query {
+        Query {
+            pattern: self.entry.get_text().unwrap_or_default(),
+            case_sensitive: self.case_button.get_active(),
+            whole_word: self.word_button.get_active(),
+            regex: self.regex_button.get_active(),
+        }
+    }
+
+    pub fn replacement(&self) -> String {
+        self.replace_entry.get_text().unwrap_or_default()
+    }
+
+    pub fn show(&self, replace: bool, text: Option<String>) {
+        if let Some(text) = text {
+            self.entry.set_text(&text);
+        }
+        self.replace_box.set_visible(replace);
+        self.revealer.set_reveal_child(true);
+        self.entry.grab_focus();
+    }
+
+    pub fn hide(&self) {
+        self.revealer.set_reveal_child(false);
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.revealer.get_reveal_child()
+    }
+
+    pub fn set_status(&self, status: &str, error: bool) {
+        self.status.set_text(status);
+        let style = self.entry.get_style_context().unwrap();
+        if error {
+            style.add_class("error");
+        } else {
+            style.remove_class("error");
+        }
+    }
+}
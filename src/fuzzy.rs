@@ -0,0 +1,80 @@
+use std::cmp;
+
+fn char_score(text: &[char], i: usize) -> i32 {
+    let word_start = i == 0 || {
+        let p = text[i - 1];
+        !p.is_alphanumeric() || (p.is_lowercase() && text[i].is_uppercase())
+    };
+    if word_start { 9 } else { 1 }
+}
+
+// Scores `text` against `query` if every character of the query appears in
+// `text` in order, ignoring case. The best alignment is chosen, favouring
+// runs of consecutive characters and matches at the start of a word.
+pub fn score(query: &str, text: &str) -> Option<i32> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).flat_map(|c| c.to_lowercase()).collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+    let text: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = text.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+    // best[i]: the best score with the current query character at text[i].
+    let mut best: Vec<Option<i32>> = vec![None; text.len()];
+    for (qi, q) in query.iter().enumerate() {
+        let mut next = vec![None; text.len()];
+        for i in 0..text.len() {
+            if lower[i] != *q {
+                continue;
+            }
+            let here = char_score(&text, i);
+            if qi == 0 {
+                next[i] = Some(here);
+                continue;
+            }
+            for (j, prev) in best.iter().enumerate().take(i) {
+                if let Some(prev) = *prev {
+                    let bonus = if j + 1 == i { 5 } else { -(cmp::min(i - j - 1, 3) as i32) };
+                    let s = prev + here + bonus;
+                    if next[i].is_none_or(|n| s > n) {
+                        next[i] = Some(s);
+                    }
+                }
+            }
+        }
+        best = next;
+    }
+
+    best.into_iter().flatten().max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_characters_do_not_match() {
+        assert_eq!(score("xyz", "window.rs"), None);
+        assert_eq!(score("sw", "window.rs"), None);
+        assert_eq!(score("", "window.rs"), Some(0));
+    }
+
+    #[test]
+    fn ignores_case_and_spaces() {
+        assert!(score("W R", "window.rs").is_some());
+        assert!(score("wr", "WINDOW.RS").is_some());
+    }
+
+    #[test]
+    fn prefix_ranks_above_scattered_match() {
+        assert!(score("win", "window.rs") > score("win", "walking.rs"));
+        assert!(score("win", "window.rs") > score("win", "awkward_input"));
+    }
+
+    #[test]
+    fn word_starts_get_a_bonus() {
+        assert!(score("b", "foo_bar") > score("b", "foobar"));
+        assert!(score("b", "fooBar") > score("b", "foobar"));
+        assert!(score("fb", "foo_bar") > score("fb", "fxxbxx"));
+    }
+}
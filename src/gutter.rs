@@ -0,0 +1,211 @@
+extern crate gtk;
+extern crate gdk;
+extern crate pango;
+
+use gtk::{
+    WidgetExt, TextViewExt, TextBufferExt, ScrollableExt,
+    AdjustmentExt, Inhibit, TextWindowType
+};
+use pango::LayoutExt;
+
+use std::cell::Cell;
+use std::cmp;
+use std::rc::Rc;
+
+const PADDING: i32 = 6;
+
+// The numbers are drawn on a child of the text view's left border window,
+// positioned from the view's own line geometry so that scrolling and
+// wrapped lines are followed.
+pub struct Gutter {
+    text_view: gtk::TextView,
+    area: gtk::DrawingArea,
+    width: Rc<Cell<i32>>,
+    visible: Rc<Cell<bool>>,
+    anchor: Rc<Cell<Option<i32>>>,
+}
+
+impl Gutter {
+    pub fn new(text_view: &gtk::TextView) -> Gutter {
+        let area = gtk::DrawingArea::new();
+        area.add_events((gdk::BUTTON_PRESS_MASK | gdk::BUTTON_RELEASE_MASK | gdk::BUTTON1_MOTION_MASK).bits() as i32);
+        area.show();
+        text_view.add_child_in_window(&area, TextWindowType::Left, 0, 0);
+
+        Gutter {
+            text_view: text_view.clone(),
+            area: area,
+            width: Rc::new(Cell::new(0)),
+            visible: Rc::new(Cell::new(false)),
+            anchor: Rc::new(Cell::new(None)),
+        }
+    }
+
+    pub fn connect(&self) {
+        let buf = self.text_view.get_buffer().unwrap();
+
+        {
+            let text_view = self.text_view.clone();
+            self.area.connect_draw(move |area, cr| {
+                draw(&text_view, area, cr);
+                Inhibit(false)
+            });
+        }
+
+        {
+            let text_view = self.text_view.clone();
+            let anchor = self.anchor.clone();
+            self.area.connect_button_press_event(move |_, event| {
+                if event.get_button() != 1 {
+                    return Inhibit(false);
+                }
+                let line = line_at(&text_view, event.get_position().1);
+                anchor.set(Some(line));
+                select_lines(&text_view, line, line);
+                text_view.grab_focus();
+                Inhibit(true)
+            });
+        }
+
+        {
+            let text_view = self.text_view.clone();
+            let anchor = self.anchor.clone();
+            self.area.connect_motion_notify_event(move |_, event| {
+                if let Some(start) = anchor.get() {
+                    select_lines(&text_view, start, line_at(&text_view, event.get_position().1));
+                }
+                Inhibit(true)
+            });
+        }
+
+        {
+            let anchor = self.anchor.clone();
+            self.area.connect_button_release_event(move |_, _| {
+                anchor.set(None);
+                Inhibit(true)
+            });
+        }
+
+        {
+            let area = self.area.clone();
+            let width = self.width.clone();
+            self.text_view.connect_size_allocate(move |_, alloc| {
+                area.set_size_request(width.get(), alloc.height);
+            });
+        }
+
+        if let Some(adj) = self.text_view.get_vadjustment() {
+            let area = self.area.clone();
+            adj.connect_value_changed(move |_| {
+                area.queue_draw();
+            });
+        }
+
+        {
+            let area = self.area.clone();
+            buf.connect_mark_set(move |buf, _, mark| {
+                if Some(mark.clone()) == buf.get_insert() {
+                    area.queue_draw();
+                }
+            });
+        }
+
+        {
+            let text_view = self.text_view.clone();
+            let area = self.area.clone();
+            let width = self.width.clone();
+            let visible = self.visible.clone();
+            buf.connect_changed(move |_| {
+                if visible.get() {
+                    update_width(&text_view, &area, &width);
+                }
+                area.queue_draw();
+            });
+        }
+    }
+
+    pub fn set_visible(&self, visible: bool) {
+        self.visible.set(visible);
+        if visible {
+            self.width.set(0);
+            update_width(&self.text_view, &self.area, &self.width);
+            self.area.show();
+        } else {
+            self.text_view.set_border_window_size(TextWindowType::Left, 0);
+            self.area.hide();
+        }
+    }
+}
+
+fn update_width(text_view: &gtk::TextView, area: &gtk::DrawingArea, width: &Cell<i32>) {
+    let lines = text_view.get_buffer().unwrap().get_line_count();
+    let digits = cmp::max(lines.to_string().len(), 2);
+    let layout = match area.create_pango_layout("9".repeat(digits).as_str()) {
+        Some(layout) => layout,
+        None => return,
+    };
+    let w = layout.get_pixel_size().0 + PADDING * 2;
+    if w != width.get() {
+        width.set(w);
+        text_view.set_border_window_size(TextWindowType::Left, w);
+        area.set_size_request(w, text_view.get_allocated_height());
+    }
+}
+
+fn draw(text_view: &gtk::TextView, area: &gtk::DrawingArea, cr: &::cairo::Context) {
+    let buf = text_view.get_buffer().unwrap();
+    let style = area.get_style_context().unwrap();
+    let layout = match area.create_pango_layout(None) {
+        Some(layout) => layout,
+        None => return,
+    };
+
+    let width = area.get_allocated_width();
+    let rect = text_view.get_visible_rect();
+    let current = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_line();
+    let (mut iter, _) = text_view.get_line_at_y(rect.y);
+
+    loop {
+        let (y, _) = text_view.get_line_yrange(&iter);
+        if y > rect.y + rect.height {
+            break;
+        }
+
+        let line = iter.get_line();
+        if line == current {
+            layout.set_markup(&format!("<b>{}</b>", line + 1));
+        } else {
+            layout.set_markup(&format!("<span foreground=\"#8e908c\">{}</span>", line + 1));
+        }
+        let (_, wy) = text_view.buffer_to_window_coords(TextWindowType::Left, 0, y);
+        let lw = layout.get_pixel_size().0;
+        gtk::render_layout(&style, cr, (width - lw - PADDING) as f64, wy as f64, &layout);
+
+        if !iter.forward_line() {
+            break;
+        }
+    }
+}
+
+fn line_at(text_view: &gtk::TextView, y: f64) -> i32 {
+    let (_, by) = text_view.window_to_buffer_coords(TextWindowType::Left, 0, y as i32);
+    text_view.get_line_at_y(by).0.get_line()
+}
+
+// Selects whole lines from `anchor` to `line`, keeping the cursor on the
+// side that is being dragged.
+fn select_lines(text_view: &gtk::TextView, anchor: i32, line: i32) {
+    let buf = text_view.get_buffer().unwrap();
+    let (first, last) = (cmp::min(anchor, line), cmp::max(anchor, line));
+    let start = buf.get_iter_at_line(first);
+    let mut end = buf.get_iter_at_line(last);
+    if !end.forward_line() {
+        end = buf.get_end_iter();
+    }
+
+    if line < anchor {
+        buf.select_range(&start, &end);
+    } else {
+        buf.select_range(&end, &start);
+    }
+}
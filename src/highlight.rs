@@ -0,0 +1,104 @@
+extern crate gtk;
+
+use gtk::{ TextBufferExt, TextTagExt, TextTagTableExt };
+
+use tokenizer::{self, Highlighter};
+
+pub const BATCH_LINES: usize = 500;
+
+fn tag_name(style: &str) -> String {
+    format!("syntax-{}", style)
+}
+
+pub fn create_tags(buf: &gtk::TextBuffer) {
+    let table = buf.get_tag_table().unwrap();
+    for style in tokenizer::STYLES {
+        let (color, bold) = match *style {
+            "comment" => ("#8e908c", false),
+            "string" => ("#718c00", false),
+            "keyword" => ("#8959a8", true),
+            "type" => ("#4271ae", false),
+            "constant" | "number" => ("#f5871f", false),
+            "function" => ("#3e999f", false),
+            "preprocessor" | "key" => ("#c82829", false),
+            "heading" => ("#4271ae", true),
+            "emphasis" => ("#8959a8", false),
+            "strong" => ("#4d4d4c", true),
+            "code" => ("#718c00", false),
+            "link" => ("#3e999f", false),
+            _ => ("#4d4d4c", false),
+        };
+
+        let tag = gtk::TextTag::new(tag_name(style).as_str());
+        tag.set_property_foreground(Some(color));
+        if bold {
+            tag.set_property_weight(700);
+        }
+        table.add(&tag);
+    }
+}
+
+// The tags of comments and strings, where brackets do not pair with those
+// outside.
+pub fn literal_tags(buf: &gtk::TextBuffer) -> Vec<gtk::TextTag> {
+    let table = buf.get_tag_table().unwrap();
+    ["comment", "string"].iter().filter_map(|style| table.lookup(&tag_name(style))).collect()
+}
+
+pub fn clear(buf: &gtk::TextBuffer) {
+    let (start, end) = buf.get_bounds();
+    for style in tokenizer::STYLES {
+        buf.remove_tag_by_name(&tag_name(style), &start, &end);
+    }
+}
+
+// Re-tokenizes at most `max` damaged lines and returns whether any are left.
+pub fn highlight_lines(buf: &gtk::TextBuffer, h: &mut Highlighter, max: usize) -> bool {
+    let mut n = 0;
+    while let Some(line) = h.next_line() {
+        if n == max {
+            break;
+        }
+        n += 1;
+
+        let start = buf.get_iter_at_line(line as i32);
+        let mut end = start.clone();
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        let text = start.get_slice(&end).unwrap_or_default();
+        let (tokens, state) = tokenizer::tokenize_line(h.language(), h.start_state(line), &text);
+
+        for style in tokenizer::STYLES {
+            buf.remove_tag_by_name(&tag_name(style), &start, &end);
+        }
+        for t in tokens {
+            let s = buf.get_iter_at_line_index(line as i32, t.start as i32);
+            let e = buf.get_iter_at_line_index(line as i32, t.end as i32);
+            buf.apply_tag_by_name(&tag_name(t.style), &s, &e);
+        }
+
+        h.update(line, state);
+    }
+
+    h.next_line().is_some()
+}
+
+// Counts line breaks the way GtkTextBuffer does.
+pub fn line_breaks(text: &str) -> usize {
+    let mut n = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                n += 1;
+            },
+            '\n' | '\u{2029}' => n += 1,
+            _ => {},
+        }
+    }
+    n
+}
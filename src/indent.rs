@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+// The indentation style found in a file. The width is unknown when the file
+// is indented with tabs only.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Indent {
+    pub spaces: bool,
+    pub width: Option<u32>,
+}
+
+const WIDTHS: &[u32] = &[2, 3, 4, 8];
+
+pub fn leading(line: &str) -> &str {
+    let end = line.find(|c| c != ' ' && c != '\t').unwrap_or(line.len());
+    &line[..end]
+}
+
+// The column `text` ends at when it starts at column 0.
+pub fn columns(text: &str, tab_width: u32) -> usize {
+    let width = tab_width.max(1) as usize;
+    text.chars().fold(0, |col, c| {
+        if c == '\t' { col + width - col % width } else { col + 1 }
+    })
+}
+
+// Whitespace reaching `columns`, with tabs for every full tab stop unless
+// indenting with spaces.
+pub fn fill(columns: usize, tab_width: u32, spaces: bool) -> String {
+    let width = tab_width.max(1) as usize;
+    if spaces {
+        " ".repeat(columns)
+    } else {
+        let mut s = "\t".repeat(columns / width);
+        s.push_str(&" ".repeat(columns % width));
+        s
+    }
+}
+
+// One level of indentation.
+pub fn unit(tab_width: u32, spaces: bool) -> String {
+    if spaces { " ".repeat(tab_width.max(1) as usize) } else { "\t".to_string() }
+}
+
+// What Tab inserts at `column`: spaces up to the next tab stop, or a tab.
+pub fn tab(column: usize, tab_width: u32, spaces: bool) -> String {
+    let width = tab_width.max(1) as usize;
+    if spaces { " ".repeat(width - column % width) } else { "\t".to_string() }
+}
+
+fn opens_block(line: &str, openers: &[String]) -> bool {
+    let line = line.trim_end();
+    openers.iter().any(|o| {
+        if o.is_empty() || !line.ends_with(o.as_str()) {
+            return false;
+        }
+        // Word openers such as "do" must not match the end of "undo".
+        let word = o.chars().next().is_some_and(|c| c.is_alphanumeric());
+        let before = line[..line.len() - o.len()].chars().next_back();
+        !word || before.is_none_or(|c| !c.is_alphanumeric() && c != '_')
+    })
+}
+
+// The indentation for a new line following `line`, one level deeper when
+// `line` ends with one of the language's `openers`.
+pub fn next_line(line: &str, openers: &[String], tab_width: u32, spaces: bool) -> String {
+    let indent = leading(line);
+    if !opens_block(line, openers) {
+        return indent.to_string();
+    }
+    let level = tab_width.max(1) as usize;
+    let current = columns(indent, tab_width);
+    fill(current - current % level + level, tab_width, spaces)
+}
+
+// The number of leading characters to remove from `line` to take it back
+// one level, to the previous tab stop.
+pub fn unindent_len(line: &str, tab_width: u32) -> usize {
+    let indent = leading(line);
+    let width = tab_width.max(1) as usize;
+    let target = match columns(indent, tab_width) {
+        0 => return 0,
+        col => (col - 1) / width * width,
+    };
+
+    // Indentation is all ASCII, so byte and character counts agree.
+    let keep = (0..indent.len() + 1)
+        .take_while(|&n| columns(&indent[..n], tab_width) <= target)
+        .last()
+        .unwrap_or(0);
+    indent.len() - keep
+}
+
+// Guesses the style from the lines that are indented: tabs if most of them
+// start with a tab, otherwise spaces with the most common step between
+// consecutive indentation levels.
+pub fn detect(text: &str) -> Option<Indent> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps: HashMap<u32, usize> = HashMap::new();
+    let mut previous = 0;
+
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = leading(line);
+        if indent.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let n = indent.len() as u32;
+        if n > 0 {
+            spaces += 1;
+        }
+        if n > previous && WIDTHS.contains(&(n - previous)) {
+            *steps.entry(n - previous).or_insert(0) += 1;
+        }
+        previous = n;
+    }
+
+    if tabs == 0 && spaces == 0 {
+        None
+    } else if tabs >= spaces {
+        Some(Indent { spaces: false, width: None })
+    } else {
+        let width = steps.into_iter().max_by_key(|&(w, n)| (n, -(w as i64))).map(|(w, _)| w);
+        Some(Indent { spaces: true, width })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn braces() -> Vec<String> {
+        vec!["{".to_string()]
+    }
+
+    #[test]
+    fn detects_tabs() {
+        assert_eq!(detect("fn a() {\n\tb();\n\t\tc();\n}\n"), Some(Indent { spaces: false, width: None }));
+    }
+
+    #[test]
+    fn detects_two_spaces() {
+        let text = "a {\n  b {\n    c\n  }\n}\n";
+        assert_eq!(detect(text), Some(Indent { spaces: true, width: Some(2) }));
+    }
+
+    #[test]
+    fn detects_mixed_by_majority() {
+        assert_eq!(detect("\ta\n    b\n    c\n"), Some(Indent { spaces: true, width: Some(4) }));
+        assert_eq!(detect("\ta\n\tb\n    c\n"), Some(Indent { spaces: false, width: None }));
+    }
+
+    #[test]
+    fn detects_nothing_without_indentation() {
+        assert_eq!(detect(""), None);
+        assert_eq!(detect("a\nb\n\n   \n"), None);
+    }
+
+    #[test]
+    fn next_line_keeps_indentation() {
+        assert_eq!(next_line("  a", &braces(), 4, true), "  ");
+        assert_eq!(next_line("\tb", &braces(), 4, false), "\t");
+    }
+
+    #[test]
+    fn next_line_indents_after_opener() {
+        assert_eq!(next_line("    if a {", &braces(), 4, true), "        ");
+        assert_eq!(next_line("   if a {", &braces(), 4, true), "    ");
+        assert_eq!(next_line("\tif a {", &braces(), 4, false), "\t\t");
+    }
+
+    #[test]
+    fn next_line_matches_word_openers_whole() {
+        let openers = vec!["do".to_string()];
+        assert_eq!(next_line("while a do", &openers, 2, true), "  ");
+        assert_eq!(next_line("undo", &openers, 2, true), "");
+    }
+
+    #[test]
+    fn unindent_to_previous_tab_stop() {
+        assert_eq!(unindent_len("        a", 4), 4);
+        assert_eq!(unindent_len("      a", 4), 2);
+        assert_eq!(unindent_len("  a", 4), 2);
+        assert_eq!(unindent_len("\t  a", 4), 2);
+        assert_eq!(unindent_len("a", 4), 0);
+    }
+
+    #[test]
+    fn tab_reaches_next_stop() {
+        assert_eq!(tab(3, 4, true), " ");
+        assert_eq!(tab(4, 4, true), "    ");
+        assert_eq!(tab(5, 0, true), " ");
+        assert_eq!(tab(5, 4, false), "\t");
+        assert_eq!(columns("\t a", 0), 3);
+    }
+}
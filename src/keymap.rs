@@ -0,0 +1,293 @@
+extern crate gtk;
+extern crate gdk;
+extern crate gio;
+extern crate glib;
+extern crate toml;
+
+use gtk::GtkApplicationExt;
+use gio::ActionGroupExt;
+use glib::ToVariant;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use prefs;
+
+// A shortcut is one or more accelerators separated by spaces, e.g.
+// "<Primary>k <Primary>c". Sequences of more than one key are chords and
+// are matched by the window itself since GtkApplication only knows single
+// accelerators.
+const DEFAULTS: &[(&str, &[&str])] = &[
+    ("app.quit", &["<Ctrl>q"]),
+    ("app.new_window", &["<Ctrl>n"]),
+    ("win.open", &["<Ctrl>o"]),
+    ("win.save", &["<Ctrl>s"]),
+    ("win.saveas", &["<Shift><Ctrl>s"]),
+    ("win.close_tab", &["<Ctrl>w"]),
+    ("win.new_tab", &["<Ctrl>t"]),
+    ("win.selectall", &["<Ctrl>a"]),
+    ("win.copy", &["<Ctrl>c"]),
+    ("win.paste", &["<Ctrl>v"]),
+    ("win.cut", &["<Ctrl>x"]),
+    ("win.undo", &["<Ctrl>z"]),
+    ("win.redo", &["<Shift><Ctrl>z"]),
+    ("win.find", &["<Ctrl>f"]),
+    ("win.replace", &["<Ctrl>h"]),
+    ("win.find_all", &["<Shift><Ctrl>f"]),
+    ("win.goto_line", &["<Ctrl>l"]),
+    ("win.command_palette", &["<Shift><Ctrl>p"]),
+    ("win.duplicate_line", &["<Shift><Ctrl>d"]),
+    ("win.delete_line", &["<Shift><Ctrl>k"]),
+    ("win.move_line_up", &["<Alt>Up"]),
+    ("win.move_line_down", &["<Alt>Down"]),
+    ("win.join_lines", &["<Ctrl>j"]),
+    ("win.add_next_occurrence", &["<Ctrl>d"]),
+    ("win.add_cursors_to_lines", &["<Shift><Ctrl>l"]),
+    ("win.jump_to_bracket", &["<Ctrl>m"]),
+    ("win.select_to_bracket", &["<Shift><Ctrl>m"]),
+];
+
+#[derive(Default, Serialize, Deserialize)]
+struct KeymapFile {
+    #[serde(default)]
+    bindings: BTreeMap<String, Vec<String>>,
+}
+
+pub enum Chord {
+    Prefix,
+    Complete(String),
+    NoMatch,
+}
+
+thread_local!(static OVERRIDES: RefCell<BTreeMap<String, Vec<String>>> = RefCell::new(BTreeMap::new()));
+thread_local!(static BINDINGS: RefCell<Option<BTreeMap<String, Vec<String>>>> = RefCell::new(None));
+
+pub fn keymap_path() -> PathBuf {
+    prefs::config_dir().join("keybindings.toml")
+}
+
+pub fn load() {
+    let mut text = String::new();
+    if fs::File::open(keymap_path()).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+        return;
+    }
+
+    match toml::from_str::<KeymapFile>(&text) {
+        Ok(file) => {
+            let bindings = file.bindings.into_iter()
+                .map(|(action, shortcuts)| (action, shortcuts.iter().filter_map(|s| parse(s)).collect()))
+                .collect();
+            OVERRIDES.with(|o| *o.borrow_mut() = bindings);
+            BINDINGS.with(|b| *b.borrow_mut() = None);
+        },
+        Err(e) => eprintln!("Cannot parse {}: {}", keymap_path().display(), e),
+    }
+}
+
+fn save() -> io::Result<()> {
+    BINDINGS.with(|b| *b.borrow_mut() = None);
+    let file = KeymapFile { bindings: OVERRIDES.with(|o| o.borrow().clone()) };
+    let text = toml::to_string(&file).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::create_dir_all(prefs::config_dir())?;
+    fs::File::create(keymap_path())?.write_all(text.as_bytes())
+}
+
+fn defaults(action: &str) -> Vec<String> {
+    DEFAULTS.iter()
+        .find(|&&(a, _)| a == action)
+        .map_or(Vec::new(), |&(_, shortcuts)| shortcuts.iter().filter_map(|s| parse(s)).collect())
+}
+
+// The defaults with the user's overrides applied, sorted by action.
+pub fn bindings() -> BTreeMap<String, Vec<String>> {
+    BINDINGS.with(|b| b.borrow_mut().get_or_insert_with(effective_bindings).clone())
+}
+
+fn effective_bindings() -> BTreeMap<String, Vec<String>> {
+    let mut bindings: BTreeMap<String, Vec<String>> = DEFAULTS.iter()
+        .map(|&(action, _)| (action.to_string(), defaults(action)))
+        .collect();
+    OVERRIDES.with(|o| {
+        for (action, shortcuts) in o.borrow().iter() {
+            bindings.insert(action.clone(), shortcuts.clone());
+        }
+    });
+    bindings.retain(|_, shortcuts| !shortcuts.is_empty());
+    bindings
+}
+
+pub fn shortcuts(action: &str) -> Vec<String> {
+    bindings().remove(action).unwrap_or_default()
+}
+
+pub fn set(action: &str, shortcuts: Vec<String>) {
+    OVERRIDES.with(|o| {
+        if shortcuts == defaults(action) {
+            o.borrow_mut().remove(action);
+        } else {
+            o.borrow_mut().insert(action.to_string(), shortcuts);
+        }
+    });
+    if let Err(e) = save() {
+        eprintln!("Cannot write {}: {}", keymap_path().display(), e);
+    }
+}
+
+pub fn reset() {
+    OVERRIDES.with(|o| o.borrow_mut().clear());
+    if let Err(e) = save() {
+        eprintln!("Cannot write {}: {}", keymap_path().display(), e);
+    }
+}
+
+// Hands the single-key shortcuts to GtkApplication. Actions that lost their
+// shortcuts are cleared as well.
+pub fn apply(app: &gtk::Application) {
+    let bindings = bindings();
+    let mut actions: Vec<String> = DEFAULTS.iter().map(|&(a, _)| a.to_string()).collect();
+    OVERRIDES.with(|o| actions.extend(o.borrow().keys().cloned()));
+    for d in app.list_action_descriptions() {
+        if !app.get_accels_for_action(&d).is_empty() {
+            actions.push(d);
+        }
+    }
+    actions.sort();
+    actions.dedup();
+
+    for action in actions {
+        let accels: Vec<&str> = bindings.get(&action).map_or(Vec::new(), |shortcuts| {
+            shortcuts.iter().filter(|s| !s.contains(' ')).map(|s| s.as_str()).collect()
+        });
+        app.set_accels_for_action(&action, &accels);
+    }
+}
+
+fn parse_key(text: &str) -> Option<String> {
+    let accel = if text.starts_with('<') {
+        text.to_string()
+    } else {
+        let mut parts: Vec<&str> = text.split('+').collect();
+        let key = parts.pop()?;
+        let mut accel = String::new();
+        for m in parts {
+            match m.to_lowercase().as_str() {
+                "ctrl" | "control" | "primary" => accel.push_str("<Control>"),
+                "shift" => accel.push_str("<Shift>"),
+                "alt" => accel.push_str("<Alt>"),
+                "super" => accel.push_str("<Super>"),
+                _ => return None,
+            }
+        }
+        accel.push_str(key);
+        accel
+    };
+
+    let (key, mods) = gtk::accelerator_parse(&accel);
+    if key == 0 {
+        return None;
+    }
+    gtk::accelerator_name(gdk::keyval_to_lower(key), mods)
+}
+
+// Accepts both accelerator syntax ("<Ctrl>k") and labels ("Ctrl+K").
+pub fn parse(text: &str) -> Option<String> {
+    let keys: Option<Vec<String>> = text.split_whitespace().map(parse_key).collect();
+    match keys {
+        Some(ref keys) if !keys.is_empty() => Some(keys.join(" ")),
+        _ => None,
+    }
+}
+
+pub fn label(shortcut: &str) -> String {
+    let keys: Vec<String> = shortcut.split(' ').map(|accel| {
+        let (key, mods) = gtk::accelerator_parse(accel);
+        let mut label = String::new();
+        if mods.contains(gdk::CONTROL_MASK) { label.push_str("Ctrl+"); }
+        if mods.contains(gdk::SHIFT_MASK) { label.push_str("Shift+"); }
+        if mods.contains(gdk::MOD1_MASK) { label.push_str("Alt+"); }
+        if mods.contains(gdk::SUPER_MASK) { label.push_str("Super+"); }
+        label.push_str(&gdk::keyval_name(gdk::keyval_to_upper(key)).unwrap_or_default());
+        label
+    }).collect();
+    keys.join(" ")
+}
+
+fn is_prefix(a: &str, b: &str) -> bool {
+    a == b || b.starts_with(&format!("{} ", a)) || a.starts_with(&format!("{} ", b))
+}
+
+// Other actions whose shortcut equals `shortcut` or would shadow it as the
+// start of a chord.
+pub fn conflicts(action: &str, shortcut: &str) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    for (a, shortcuts) in bindings() {
+        if a == action {
+            continue;
+        }
+        for s in shortcuts {
+            if is_prefix(&s, shortcut) {
+                found.push((a.clone(), s));
+            }
+        }
+    }
+    found
+}
+
+pub fn event_key(event: &gdk::EventKey) -> Option<String> {
+    let key = gdk::keyval_to_lower(event.get_keyval());
+    let mods = event.get_state() & gtk::accelerator_get_default_mod_mask();
+    if !gtk::accelerator_valid(key, mods) {
+        return None;
+    }
+    gtk::accelerator_name(key, mods)
+}
+
+// Single-key shortcuts are left to GtkApplication.
+pub fn lookup_chord(keys: &[String]) -> Chord {
+    let seq = keys.join(" ");
+    let prefix = format!("{} ", seq);
+    let mut is_prefix = false;
+    for (action, shortcuts) in bindings() {
+        for s in shortcuts.iter().filter(|s| s.contains(' ')) {
+            if *s == seq {
+                return Chord::Complete(action.clone());
+            }
+            if s.starts_with(&prefix) {
+                is_prefix = true;
+            }
+        }
+    }
+    if is_prefix { Chord::Prefix } else { Chord::NoMatch }
+}
+
+// Activates "app.name", "win.name" or a detailed "win.name::target".
+pub fn activate(app: &gtk::Application, win: &gtk::ApplicationWindow, detailed: &str) {
+    let (name, target) = match detailed.find("::") {
+        Some(i) => (&detailed[..i], Some(detailed[i + 2..].to_variant())),
+        None => (detailed, None),
+    };
+    if name.starts_with("app.") {
+        app.activate_action(&name[4..], target.as_ref());
+    } else if name.starts_with("win.") {
+        win.activate_action(&name[4..], target.as_ref());
+    }
+}
+
+// Every action of the application and the window that can be bound
+// without a parameter.
+pub fn actions(app: &gtk::Application, win: Option<&gtk::ApplicationWindow>) -> Vec<String> {
+    let mut actions: Vec<String> = app.list_actions().into_iter()
+        .filter(|a| app.get_action_parameter_type(a).is_none())
+        .map(|a| format!("app.{}", a))
+        .collect();
+    if let Some(win) = win {
+        actions.extend(win.list_actions().into_iter()
+            .filter(|a| win.get_action_parameter_type(a).is_none())
+            .map(|a| format!("win.{}", a)));
+    }
+    actions.sort();
+    actions
+}
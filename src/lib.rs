@@ -0,0 +1,82 @@
+extern crate encoding_rs;
+extern crate regex;
+extern crate chrono;
+#[cfg(feature = "gui")]
+extern crate gtk;
+#[cfg(feature = "gui")]
+extern crate gio;
+#[cfg(feature = "gui")]
+extern crate gdk;
+#[cfg(feature = "gui")]
+extern crate gdk_pixbuf;
+#[cfg(feature = "gui")]
+extern crate cairo;
+#[cfg(feature = "gui")]
+extern crate pango;
+#[cfg(feature = "gui")]
+extern crate glib;
+#[cfg(feature = "gui")]
+extern crate gio_sys;
+#[cfg(feature = "gui")]
+extern crate glib_sys;
+#[cfg(feature = "gui")]
+extern crate gobject_sys;
+#[cfg(feature = "gui")]
+extern crate toml;
+#[cfg(feature = "gui")]
+extern crate serde;
+#[cfg(feature = "gui")]
+#[macro_use]
+extern crate serde_derive;
+
+pub mod encoding;
+pub mod eol;
+pub mod undo;
+pub mod search;
+pub mod location;
+pub mod fuzzy;
+pub mod indent;
+pub mod lines;
+pub mod block;
+pub mod carets;
+pub mod brackets;
+pub mod tokenizer;
+pub mod document;
+pub mod registry;
+
+#[cfg(feature = "gui")]
+pub mod app;
+#[cfg(feature = "gui")]
+pub mod resources;
+#[cfg(feature = "gui")]
+pub mod win;
+#[cfg(feature = "gui")]
+pub mod page;
+#[cfg(feature = "gui")]
+pub mod find_bar;
+#[cfg(feature = "gui")]
+pub mod results_panel;
+#[cfg(feature = "gui")]
+pub mod prefs;
+#[cfg(feature = "gui")]
+pub mod recovery;
+#[cfg(feature = "gui")]
+pub mod session;
+#[cfg(feature = "gui")]
+pub mod recent;
+#[cfg(feature = "gui")]
+pub mod syntax;
+#[cfg(feature = "gui")]
+pub mod highlight;
+#[cfg(feature = "gui")]
+pub mod gutter;
+#[cfg(feature = "gui")]
+pub mod status_bar;
+#[cfg(feature = "gui")]
+pub mod prefs_dialog;
+#[cfg(feature = "gui")]
+pub mod keymap;
+#[cfg(feature = "gui")]
+pub mod shortcuts_dialog;
+#[cfg(feature = "gui")]
+pub mod palette;
@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineOp {
+    Duplicate,
+    Delete,
+    MoveUp,
+    MoveDown,
+    Join,
+    Sort,
+    SortNumeric,
+    SortReverse,
+    Unique,
+    Reverse,
+}
+
+// The lines replacing a block and which of them, first to last, end up
+// selected. No lines means the block is removed with its line break.
+pub struct LineEdit {
+    pub lines: Vec<String>,
+    pub select: (usize, usize),
+}
+
+impl LineOp {
+    // Moving and joining a single line take in the neighbouring line, so the
+    // block handed to `apply` grows by that many lines above and below.
+    fn context(&self, lines: usize) -> (usize, usize) {
+        match *self {
+            LineOp::MoveUp => (1, 0),
+            LineOp::MoveDown => (0, 1),
+            LineOp::Join if lines == 1 => (0, 1),
+            _ => (0, 0),
+        }
+    }
+
+    // The lines, first to last, handed to `apply` for a selection over
+    // lines `first` to `last` of `count`, or None when there is no line to
+    // move past or join with. A selection ending at the start of a line
+    // leaves that line out.
+    pub fn lines(&self, first: usize, last: usize, ends_at_line_start: bool, count: usize) -> Option<(usize, usize)> {
+        let last = if last > first && ends_at_line_start { last - 1 } else { last };
+        let (above, below) = self.context(last - first + 1);
+        if first < above || last + below >= count {
+            return None;
+        }
+        Some((first - above, last + below))
+    }
+
+    pub fn apply(&self, block: &[&str]) -> LineEdit {
+        let n = block.len();
+        let mut lines: Vec<String> = block.iter().map(|l| l.to_string()).collect();
+        let select = match *self {
+            LineOp::Duplicate => {
+                lines.extend(block.iter().map(|l| l.to_string()));
+                (n, 2 * n - 1)
+            },
+            LineOp::Delete => {
+                lines.clear();
+                (0, 0)
+            },
+            LineOp::MoveUp => {
+                let above = lines.remove(0);
+                lines.push(above);
+                (0, n - 2)
+            },
+            LineOp::MoveDown => {
+                let below = lines.pop().unwrap();
+                lines.insert(0, below);
+                (1, n - 1)
+            },
+            LineOp::Join => {
+                lines = vec![join(block)];
+                (0, 0)
+            },
+            LineOp::Sort => {
+                lines.sort();
+                (0, n - 1)
+            },
+            LineOp::SortNumeric => {
+                lines.sort_by(|a, b| compare_numeric(a, b));
+                (0, n - 1)
+            },
+            LineOp::SortReverse => {
+                lines.sort_by(|a, b| b.cmp(a));
+                (0, n - 1)
+            },
+            LineOp::Unique => {
+                let mut seen = HashSet::new();
+                lines.retain(|l| seen.insert(l.clone()));
+                (0, lines.len() - 1)
+            },
+            LineOp::Reverse => {
+                lines.reverse();
+                (0, n - 1)
+            },
+        };
+
+        LineEdit { lines, select }
+    }
+}
+
+// Later lines lose their indentation and are separated by a single space.
+fn join(block: &[&str]) -> String {
+    let mut joined = String::new();
+    for (i, line) in block.iter().enumerate() {
+        let line = if i == 0 { line.trim_end() } else { line.trim() };
+        if !joined.is_empty() && !line.is_empty() {
+            joined.push(' ');
+        }
+        joined.push_str(line);
+    }
+    joined
+}
+
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let end = line.char_indices()
+        .take_while(|&(i, c)| c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+')))
+        .last()
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    line[..end].parse().ok()
+}
+
+// Lines starting with a number come first, in numeric order; the others
+// follow in their original order.
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+    match (leading_number(a), leading_number(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(op: LineOp, block: &[&str]) -> Vec<String> {
+        op.apply(block).lines
+    }
+
+    #[test]
+    fn sorts_numbers_by_value() {
+        let block = ["10 b", "9", "b", "1.5", "-2", "a"];
+        assert_eq!(apply(LineOp::Sort, &block), vec!["-2", "1.5", "10 b", "9", "a", "b"]);
+        assert_eq!(apply(LineOp::SortNumeric, &block), vec!["-2", "1.5", "9", "10 b", "b", "a"]);
+        assert_eq!(apply(LineOp::SortReverse, &block), vec!["b", "a", "9", "10 b", "1.5", "-2"]);
+    }
+
+    #[test]
+    fn unique_keeps_first_occurrences_in_order() {
+        let edit = LineOp::Unique.apply(&["b", "a", "b", "c", "a"]);
+        assert_eq!(edit.lines, vec!["b", "a", "c"]);
+        assert_eq!(edit.select, (0, 2));
+    }
+
+    #[test]
+    fn moves_lines_past_neighbour() {
+        assert_eq!(LineOp::MoveUp.lines(1, 2, false, 4), Some((0, 2)));
+        assert_eq!(apply(LineOp::MoveUp, &["a", "b", "c"]), vec!["b", "c", "a"]);
+        assert_eq!(LineOp::MoveDown.lines(1, 2, false, 4), Some((1, 3)));
+        assert_eq!(apply(LineOp::MoveDown, &["b", "c", "d"]), vec!["d", "b", "c"]);
+    }
+
+    #[test]
+    fn first_and_last_lines_stay_put() {
+        assert_eq!(LineOp::MoveUp.lines(0, 1, false, 3), None);
+        assert_eq!(LineOp::MoveDown.lines(1, 2, false, 3), None);
+        assert_eq!(LineOp::Join.lines(2, 2, false, 3), None);
+        assert_eq!(LineOp::MoveDown.lines(0, 0, false, 3), Some((0, 1)));
+    }
+
+    #[test]
+    fn selection_ending_at_line_start_leaves_line_out() {
+        assert_eq!(LineOp::Sort.lines(0, 2, true, 3), Some((0, 1)));
+        assert_eq!(LineOp::Sort.lines(2, 2, true, 3), Some((2, 2)));
+    }
+
+    #[test]
+    fn joins_without_extra_whitespace() {
+        assert_eq!(apply(LineOp::Join, &["  a  ", "\tb \t", "", "c"]), vec!["  a b c"]);
+        assert_eq!(apply(LineOp::Join, &["a ", ""]), vec!["a"]);
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        // The buffer "a\nb" has no line after "b" to take a line break from.
+        let edit = LineOp::Duplicate.apply(&["b"]);
+        assert_eq!(edit.lines.join("\n"), "b\nb");
+        assert_eq!(edit.select, (1, 1));
+        assert_eq!(LineOp::Delete.apply(&["b"]).lines.len(), 0);
+        assert_eq!(LineOp::Delete.lines(1, 1, false, 2), Some((1, 1)));
+    }
+}
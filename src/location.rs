@@ -0,0 +1,97 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+fn split_number(s: &str) -> Option<(&str, u32)> {
+    let i = s.rfind(':')?;
+    let n = s[i + 1..].parse::<u32>().ok()?;
+    Some((&s[..i], n))
+}
+
+// Parses "line" or "line:column", both counted from 1.
+pub fn parse_position(text: &str) -> Option<Position> {
+    let text = text.trim();
+    let (line, column) = match text.find(':') {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+
+    let line = line.trim().parse::<u32>().ok()?;
+    let column = match column {
+        Some(c) => Some(c.trim().parse::<u32>().ok()?),
+        None => None,
+    };
+    Some(Position { line, column })
+}
+
+// Splits "path:line" or "path:line:column" as printed by compilers and grep.
+// A trailing colon is ignored.
+pub fn split_location(arg: &str) -> Option<(&str, Position)> {
+    let s = arg.trim_end_matches(':');
+    let (rest, last) = split_number(s)?;
+    if rest.is_empty() {
+        return None;
+    }
+
+    match split_number(rest) {
+        Some((path, line)) if !path.is_empty() => {
+            Some((path, Position { line, column: Some(last) }))
+        },
+        _ => Some((rest, Position { line: last, column: None })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: u32, column: Option<u32>) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn parses_positions() {
+        assert_eq!(parse_position("12"), Some(pos(12, None)));
+        assert_eq!(parse_position(" 12 : 5 "), Some(pos(12, Some(5))));
+        assert_eq!(parse_position("0"), Some(pos(0, None)));
+        assert_eq!(parse_position(""), None);
+        assert_eq!(parse_position("12:"), None);
+        assert_eq!(parse_position("x"), None);
+        assert_eq!(parse_position("12:x"), None);
+        assert_eq!(parse_position("-1"), None);
+    }
+
+    #[test]
+    fn splits_line_and_column() {
+        assert_eq!(split_location("file.rs:12"), Some(("file.rs", pos(12, None))));
+        assert_eq!(split_location("file.rs:12:5"), Some(("file.rs", pos(12, Some(5)))));
+        assert_eq!(split_location("file.rs:12:5:"), Some(("file.rs", pos(12, Some(5)))));
+        assert_eq!(split_location("file.rs:0"), Some(("file.rs", pos(0, None))));
+    }
+
+    #[test]
+    fn plain_names_have_no_location() {
+        assert_eq!(split_location("file.rs"), None);
+        assert_eq!(split_location("file.rs:"), None);
+        assert_eq!(split_location("file.rs:x"), None);
+        assert_eq!(split_location("file.rs:12:x"), None);
+        assert_eq!(split_location(":12"), None);
+    }
+
+    #[test]
+    fn keeps_colons_in_names() {
+        assert_eq!(split_location("C:\\src\\main.rs"), None);
+        assert_eq!(split_location("C:\\src\\main.rs:3"), Some(("C:\\src\\main.rs", pos(3, None))));
+        assert_eq!(split_location("a:b.txt:3:4"), Some(("a:b.txt", pos(3, Some(4)))));
+        assert_eq!(split_location("notes:2020:7"), Some(("notes", pos(2020, Some(7)))));
+    }
+
+    #[test]
+    fn splits_uris() {
+        assert_eq!(split_location("file:///tmp/a.rs"), None);
+        assert_eq!(split_location("file:///tmp/a.rs:8:2"), Some(("file:///tmp/a.rs", pos(8, Some(2)))));
+        assert_eq!(split_location("sftp://host:22/a.rs:8"), Some(("sftp://host:22/a.rs", pos(8, None))));
+    }
+}
@@ -1,41 +1,113 @@
 extern crate gtk;
 extern crate gio;
+extern crate gdk;
+extern crate pango;
+extern crate glib;
+extern crate gio_sys;
+extern crate glib_sys;
+extern crate gobject_sys;
 
 use gtk::{
     ResponseType, LabelExt, TextViewExt,
-    TextBufferExt, NotebookExt, ButtonExt, 
+    TextBufferExt, TextBufferExtManual,
+    NotebookExt, ButtonExt,
     WidgetExt, FileChooserExt, DialogExt,
-    ContainerExt, InfoBarExt,
-    RevealerExt, BinExt, Cast
+    ContainerExt, InfoBarExt, ComboBoxExt,
+    ComboBoxTextExt, WindowExt, RevealerExt,
+    TextTagExt, TextTagTableExt, BinExt, Cast,
+    ClipboardExt, ScrollableExt, AdjustmentExt, StyleContextExt,
+    IsA, Continue, Inhibit
 };
 
 use gio::{
-    FileExt
+    FileExt, FileInfoExt
 };
 
+use pango::LayoutExt;
+
+use glib::translate::{from_glib_full, ToGlibPtr};
+
 use std::cell::RefCell;
+use std::cmp;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::ptr;
 use std::rc::Rc;
-use std::ops::Deref;
-use std::path::Path;
+
+use encoding_rs::Encoding;
 
 use win::{
     Window, Windows, WindowExtend, WindowsExtend
 };
+use encoding;
+use eol::LineEnding;
+use undo::Edit;
+use document::{Document, DiskChange, CloseResponse, CloseAction, SaveError, Storage};
+use registry::Registry;
+use find_bar::FindBar;
+use prefs::{self, BackupMode, Preferences};
+use resources;
+use recovery::{self, Entry};
+use recent;
+use syntax;
+use tokenizer::{Language, Highlighter};
+use highlight;
+use gutter::Gutter;
+use location::Position;
+use indent::{self, Indent};
+use lines::LineOp;
+use block::{self, Block};
+use carets;
+use brackets;
+use search::Match;
+
+const FLASH_MILLIS: u32 = 800;
+// How far to look for a matching bracket.
+const BRACKET_SCAN_CHARS: usize = 20000;
+
+// The text last copied from a block, to tell block clipboard content from
+// ordinary text when pasting.
+thread_local!(static BLOCK_CLIPBOARD: RefCell<Option<String>> = RefCell::new(None));
 
 pub struct PageCore {
     tab: gtk::Box,
     tab_label: gtk::Label,
     revealer: gtk::Revealer,
+    info_bar: gtk::InfoBar,
+    info_label: gtk::Label,
+    reload_button: gtk::Button,
+    keep_button: gtk::Button,
     contents: gtk::Box,
     text_view: gtk::TextView,
     close_button: gtk::Button,
-    file: Option<gio::File>,
-    changed: bool,
+    gutter: Gutter,
+    doc: Document,
+    find_bar: FindBar,
+    find_pending: bool,
+    recovery_id: String,
+    snapshot_dirty: bool,
+    has_snapshot: bool,
+    highlighter: Option<Highlighter>,
+    highlight_pending: bool,
+    indent_width: u32,
+    indent_spaces: bool,
+    detected_indent: Option<Indent>,
+    highlight_line: bool,
+    block: Option<Block>,
+    block_drag: bool,
+    block_busy: bool,
+    carets: Vec<(gtk::TextMark, gtk::TextMark)>,
+    caret_area: gtk::DrawingArea,
+    carets_busy: bool,
+    auto_close: bool,
+    closers: Vec<gtk::TextMark>,
+    matched: Vec<gtk::TextMark>,
+    brackets_pending: bool,
 }
 
 impl PageCore {
     pub fn new() -> PageCore {
-        let builder = gtk::Builder::new_from_file(Path::new("/usr/share/vanilla_text/ui/page.ui"));
+        let builder = resources::builder("page.ui");
         let tab: gtk::Box = builder.get_object("tab").unwrap();
         let label: gtk::Label = builder.get_object("label").unwrap();
         let close_button: gtk::Button = builder.get_object("close_button").unwrap();
@@ -54,18 +126,69 @@ impl PageCore {
             });
         }
 
-        let scr_win = contents.get_children()[1].clone().downcast::<gtk::ScrolledWindow>().ok().unwrap();
-        let txt_view = scr_win.get_child().unwrap().downcast::<gtk::TextView>().ok().unwrap();
+        let txt_view: gtk::TextView = builder.get_object("txt_view").unwrap();
+
+        let line_tag = gtk::TextTag::new("current-line");
+        line_tag.set_property_paragraph_background(Some("#f2f2f2"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&line_tag);
+
+        let flash_tag = gtk::TextTag::new("goto-flash");
+        flash_tag.set_property_paragraph_background(Some("#fce94f"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&flash_tag);
+
+        let match_tag = gtk::TextTag::new("search-match");
+        match_tag.set_property_background(Some("yellow"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&match_tag);
+
+        let block_tag = gtk::TextTag::new("block-selection");
+        block_tag.set_property_background(Some("#b5d5ff"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&block_tag);
+
+        let caret_tag = gtk::TextTag::new("caret-selection");
+        caret_tag.set_property_background(Some("#b5d5ff"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&caret_tag);
+
+        let bracket_tag = gtk::TextTag::new("bracket-match");
+        bracket_tag.set_property_background(Some("#c5e1a5"));
+        txt_view.get_buffer().unwrap().get_tag_table().unwrap().add(&bracket_tag);
+        highlight::create_tags(&txt_view.get_buffer().unwrap());
+
+        let gutter = Gutter::new(&txt_view);
 
         PageCore {
             tab: tab,
             tab_label: label,
             revealer: revealer,
+            info_bar: info_bar,
+            info_label: builder.get_object("info").unwrap(),
+            reload_button: builder.get_object("reload_button").unwrap(),
+            keep_button: builder.get_object("keep_button").unwrap(),
             contents: contents,
             text_view: txt_view,
             close_button: close_button,
-            file: None,
-            changed: false,
+            gutter: gutter,
+            doc: Document::new(),
+            find_bar: FindBar::new(&builder),
+            find_pending: false,
+            recovery_id: recovery::new_id(),
+            snapshot_dirty: false,
+            has_snapshot: false,
+            highlighter: None,
+            highlight_pending: false,
+            indent_width: 4,
+            indent_spaces: false,
+            detected_indent: None,
+            highlight_line: true,
+            block: None,
+            block_drag: false,
+            block_busy: false,
+            carets: Vec::new(),
+            caret_area: builder.get_object("caret_area").unwrap(),
+            carets_busy: false,
+            auto_close: true,
+            closers: Vec::new(),
+            matched: Vec::new(),
+            brackets_pending: false,
         }
     }
 
@@ -85,14 +208,92 @@ pub trait PageExtend {
     fn close_button(&self) -> gtk::Button;
     fn changed(&self) -> bool;
     fn set_changed(&self, changed: bool);
+    fn encoding(&self) -> &'static Encoding;
+    fn set_encoding(&self, encoding: &'static Encoding);
+    fn line_ending(&self) -> LineEnding;
+    fn set_line_ending(&self, line_ending: LineEnding);
     fn is_empty(&self) -> bool;
-    fn load_file(&self, file: &gio::File);
+    fn text(&self) -> String;
+    fn select_offsets(&self, start: usize, end: usize);
+    fn load_file(&self, file: &gio::File) -> Result<(), String>;
+    fn load_file_with_encoding(&self, file: &gio::File, encoding: Option<&'static Encoding>) -> Result<(), String>;
+    fn reopen_with_encoding(&self, win: Window);
+    fn save_with_encoding(&self, wins: Windows, win: Window) -> bool;
+    fn encoding_chooser_run(&self, win: Window, title: &str) -> Option<&'static Encoding>;
     fn save_confirm(&self, wins: Windows, win: Window) -> bool;
     fn save_file(&self, wins: Windows, win: Window) -> bool;
-    fn save_buffer(&self, win: Window);
+    fn save_buffer(&self, win: Window) -> bool;
     fn save_as(&self, wins: Windows, win: Window) -> bool;
     fn save_file_chooser_run(&self, win: Window) -> Option<gio::File>;
     fn show_warning(&self);
+    fn show_info(&self, message: &str, actions: bool);
+    fn hide_info(&self);
+    fn check_file(&self);
+    fn reload(&self);
+    fn autosave(&self) -> bool;
+    fn snapshot_dirty(&self) -> bool;
+    fn set_snapshot_dirty(&self, dirty: bool);
+    fn recovery_entry(&self, window: usize) -> Entry;
+    fn discard_snapshot(&self);
+    fn restore(&self, entry: &Entry, text: &str);
+    fn view_position(&self) -> (i32, i32);
+    fn set_view_position(&self, cursor: i32, top: i32);
+    fn undo(&self);
+    fn redo(&self);
+    fn apply_edits(&self, edits: Vec<Edit>);
+    fn show_find_bar(&self, replace: bool);
+    fn hide_find_bar(&self);
+    fn search_matches(&self) -> Option<Vec<Match>>;
+    fn update_matches(&self);
+    fn queue_update_matches(&self);
+    fn find_next(&self, forward: bool);
+    fn replace_match(&self);
+    fn replace_all_matches(&self);
+    fn set_language(&self, language: Option<Rc<Language>>);
+    fn detect_language(&self);
+    fn queue_highlight(&self);
+    fn language(&self) -> Option<Rc<Language>>;
+    fn indent_width(&self) -> u32;
+    fn set_indent_width(&self, width: u32);
+    fn indent_spaces(&self) -> bool;
+    fn set_indent_spaces(&self, spaces: bool);
+    fn insert_tab(&self) -> bool;
+    fn insert_newline(&self) -> bool;
+    fn indent_lines(&self, deeper: bool) -> bool;
+    fn detect_indent(&self, text: &str);
+    fn edit_lines(&self, op: LineOp);
+    fn block(&self) -> Option<Block>;
+    fn set_block(&self, block: Option<Block>);
+    fn cursor_point(&self) -> (usize, usize);
+    fn block_key(&self, event: &gdk::EventKey) -> bool;
+    fn block_text(&self) -> Option<String>;
+    fn replace_block(&self, text: &[String]);
+    fn copy_block(&self, clipboard: &gtk::Clipboard) -> bool;
+    fn cut_block(&self, clipboard: &gtk::Clipboard) -> bool;
+    fn paste_block(&self, clipboard: &gtk::Clipboard) -> bool;
+    fn caret_marks(&self) -> Vec<(gtk::TextMark, gtk::TextMark)>;
+    fn has_carets(&self) -> bool;
+    fn add_caret(&self, head: &gtk::TextIter, anchor: &gtk::TextIter);
+    fn click_caret(&self, iter: &gtk::TextIter);
+    fn clear_carets(&self);
+    fn update_carets(&self);
+    fn add_next_occurrence(&self);
+    fn add_carets_to_lines(&self);
+    fn carets_key(&self, event: &gdk::EventKey) -> bool;
+    fn edit_carets<F: Fn(&gtk::TextBuffer, usize, &mut gtk::TextIter, &mut gtk::TextIter)>(&self, edit: F);
+    fn move_carets(&self, keyval: u32, extend: bool);
+    fn paste_carets(&self, clipboard: &gtk::Clipboard) -> bool;
+    fn matching_bracket(&self) -> Option<(gtk::TextIter, gtk::TextIter)>;
+    fn update_brackets(&self);
+    fn queue_update_brackets(&self);
+    fn jump_to_bracket(&self);
+    fn select_to_bracket(&self);
+    fn type_char(&self, c: char) -> bool;
+    fn delete_pair(&self) -> bool;
+    fn set_line_numbers(&self, visible: bool);
+    fn apply_prefs(&self, prefs: &Preferences);
+    fn update_current_line(&self);
+    fn goto(&self, pos: Position);
 }
 
 impl PageExtend for Page {
@@ -113,22 +314,209 @@ impl PageExtend for Page {
         {
             let p = page.clone();
             page.text_view().get_buffer().unwrap().connect_changed(move |_| {
+                if p.borrow().find_bar.is_visible() {
+                    p.queue_update_matches();
+                }
+                p.borrow_mut().snapshot_dirty = true;
+                p.update_current_line();
+                p.queue_update_brackets();
+                if p.block().is_some() && !p.borrow().block_busy {
+                    p.set_block(None);
+                }
+                if p.has_carets() && !p.borrow().carets_busy {
+                    p.update_carets();
+                }
                 if p.changed() {
                     return;
                 }
-                p.borrow_mut().changed = true;
+                p.set_changed(true);
+            });
+        }
+
+        page.borrow().find_bar.connect(&page);
+        page.borrow().gutter.connect();
+        page.apply_prefs(&prefs::get());
+
+        {
+            let p = page.clone();
+            page.text_view().connect_key_press_event(move |_, event| {
+                if p.block_key(event) || p.carets_key(event) {
+                    return Inhibit(true);
+                }
+                let modifiers = event.get_state() & (gdk::SHIFT_MASK | gdk::CONTROL_MASK | gdk::MOD1_MASK);
+                let handled = match event.get_keyval() {
+                    gdk::enums::key::Tab if modifiers.is_empty() => p.indent_lines(true) || p.insert_tab(),
+                    gdk::enums::key::ISO_Left_Tab | gdk::enums::key::Tab if modifiers == gdk::SHIFT_MASK => p.indent_lines(false),
+                    gdk::enums::key::Return | gdk::enums::key::KP_Enter if modifiers.is_empty() => p.insert_newline(),
+                    gdk::enums::key::BackSpace if modifiers.is_empty() => p.delete_pair(),
+                    keyval => match gdk::keyval_to_unicode(keyval) {
+                        Some(c) if !c.is_control() && (modifiers & (gdk::CONTROL_MASK | gdk::MOD1_MASK)).is_empty() => p.type_char(c),
+                        _ => false,
+                    },
+                };
+                Inhibit(handled)
+            });
+        }
+
+        {
+            let p = page.clone();
+            let buf = page.text_view().get_buffer().unwrap();
+            buf.connect_mark_set(move |buf, _, mark| {
+                if Some(mark.clone()) == buf.get_insert() {
+                    p.update_current_line();
+                    p.queue_update_brackets();
+                    if p.block().is_some() && !p.borrow().block_busy {
+                        p.set_block(None);
+                    }
+                    if p.has_carets() && !p.borrow().carets_busy {
+                        p.clear_carets();
+                    }
+                }
+            });
+        }
+
+        {
+            let p = page.clone();
+            page.text_view().connect_button_press_event(move |text_view, event| {
+                let modifiers = event.get_state() & (gdk::SHIFT_MASK | gdk::CONTROL_MASK | gdk::MOD1_MASK);
+                if event.get_button() != 1 || modifiers != gdk::CONTROL_MASK ||
+                    event.get_window() != TextViewExt::get_window(text_view, gtk::TextWindowType::Text) {
+                    return Inhibit(false);
+                }
+                let (x, y) = event.get_position();
+                let (x, y) = text_view.window_to_buffer_coords(gtk::TextWindowType::Text, x as i32, y as i32);
+                if let Some((mut iter, trailing)) = text_view.get_iter_at_position(x, y) {
+                    iter.forward_chars(trailing);
+                    text_view.grab_focus();
+                    p.click_caret(&iter);
+                }
+                Inhibit(true)
+            });
+        }
+
+        {
+            let p = page.clone();
+            let text_view = page.text_view();
+            page.borrow().caret_area.connect_draw(move |area, cr| {
+                draw_carets(&text_view, area, cr, &p.borrow().carets);
+                Inhibit(false)
+            });
+        }
+
+        for adj in &[page.text_view().get_vadjustment(), page.text_view().get_hadjustment()] {
+            if let Some(ref adj) = *adj {
+                let area = page.borrow().caret_area.clone();
+                adj.connect_value_changed(move |_| {
+                    area.queue_draw();
+                });
+            }
+        }
+
+        {
+            let p = page.clone();
+            page.text_view().connect_button_press_event(move |text_view, event| {
+                let modifiers = event.get_state() & (gdk::SHIFT_MASK | gdk::CONTROL_MASK | gdk::MOD1_MASK);
+                if event.get_button() != 1 || modifiers != gdk::MOD1_MASK ||
+                    event.get_window() != TextViewExt::get_window(text_view, gtk::TextWindowType::Text) {
+                    return Inhibit(false);
+                }
+                let (x, y) = event.get_position();
+                let point = block_point(text_view, x, y, p.indent_width());
+                text_view.grab_focus();
+                p.set_block(Some(Block::new(point, point)));
+                p.borrow_mut().block_drag = true;
+                Inhibit(true)
+            });
+        }
+
+        {
+            let p = page.clone();
+            page.text_view().connect_motion_notify_event(move |text_view, event| {
+                let anchor = match p.block() {
+                    Some(block) if p.borrow().block_drag => block.anchor,
+                    _ => return Inhibit(false),
+                };
+                let (x, y) = event.get_position();
+                let point = block_point(text_view, x, y, p.indent_width());
+                p.set_block(Some(Block::new(anchor, point)));
+                Inhibit(true)
+            });
+        }
+
+        {
+            let p = page.clone();
+            page.text_view().connect_button_release_event(move |_, _| {
+                let dragging = p.borrow().block_drag;
+                p.borrow_mut().block_drag = false;
+                Inhibit(dragging)
+            });
+        }
+
+        {
+            let p = page.clone();
+            page.borrow().info_bar.connect_response(move |_, sig| {
+                if sig == ResponseType::Accept.into() {
+                    p.reload();
+                } else if sig == ResponseType::Reject.into() {
+                    p.hide_info();
+                }
             });
         }
 
+        {
+            let buf = page.text_view().get_buffer().unwrap();
+            {
+                let p = page.clone();
+                buf.connect_insert_text(move |_, iter, text| {
+                    p.borrow_mut().doc.undo.record(Edit::Insert {
+                        offset: iter.get_offset() as usize,
+                        text: text.to_string(),
+                    });
+                    if let Some(ref mut h) = p.borrow_mut().highlighter {
+                        h.edit(iter.get_line() as usize, 0, highlight::line_breaks(text));
+                    }
+                    p.queue_highlight();
+                });
+            }
+            {
+                let p = page.clone();
+                buf.connect_delete_range(move |_, start, end| {
+                    if let Some(text) = start.get_slice(end) {
+                        p.borrow_mut().doc.undo.record(Edit::Delete {
+                            offset: start.get_offset() as usize,
+                            text: text,
+                        });
+                    }
+                    if let Some(ref mut h) = p.borrow_mut().highlighter {
+                        let removed = (end.get_line() - start.get_line()) as usize;
+                        h.edit(start.get_line() as usize, removed, 0);
+                    }
+                    p.queue_highlight();
+                });
+            }
+            {
+                let p = page.clone();
+                buf.connect_begin_user_action(move |_| {
+                    p.borrow_mut().doc.undo.begin_group();
+                });
+            }
+            {
+                let p = page.clone();
+                buf.connect_end_user_action(move |_| {
+                    p.borrow_mut().doc.undo.end_group();
+                });
+            }
+        }
+
         page
     }
 
     fn file(&self) -> Option<gio::File> {
-        self.borrow().file.clone()
+        self.borrow().doc.uri().map(gio::File::new_for_uri)
     }
 
     fn set_file(&self, file: Option<gio::File>) {
-        self.borrow_mut().file = file;
+        self.borrow_mut().doc.set_uri(file.and_then(|f| f.get_uri()));
     }
 
     fn contents(&self) -> gtk::Box {
@@ -152,11 +540,27 @@ impl PageExtend for Page {
     }
 
     fn changed(&self) -> bool {
-        self.borrow().changed
+        self.borrow().doc.modified()
     }
 
     fn set_changed(&self, changed: bool) {
-        self.borrow_mut().changed = changed;
+        self.borrow_mut().doc.set_modified(changed);
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        self.borrow().doc.encoding()
+    }
+
+    fn set_encoding(&self, encoding: &'static Encoding) {
+        self.borrow_mut().doc.set_encoding(encoding);
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.borrow().doc.line_ending()
+    }
+
+    fn set_line_ending(&self, line_ending: LineEnding) {
+        self.borrow_mut().doc.set_line_ending(line_ending);
     }
 
     fn is_empty(&self) -> bool {
@@ -169,21 +573,111 @@ impl PageExtend for Page {
         start == end
     }
 
-    fn load_file(&self, file: &gio::File) {
-        if let Ok((v, _)) = file.load_contents(None) {
-            let text = String::from_utf8(v).unwrap();
+    fn text(&self) -> String {
+        let buf = self.text_view().get_buffer().unwrap();
+        let (start, end) = buf.get_bounds();
+        buf.get_text(&start, &end, true).unwrap_or_default()
+    }
 
-            let buf = self.borrow().text_view.get_buffer().unwrap();
-            buf.set_text(&text);
-            self.tab_label().set_text(file.get_basename().unwrap().to_str().unwrap());
-            self.set_file(Some(file.clone()));
-            self.set_changed(false);
+    fn select_offsets(&self, start: usize, end: usize) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let start = buf.get_iter_at_offset(start as i32);
+        let end = buf.get_iter_at_offset(end as i32);
+        buf.select_range(&start, &end);
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    fn load_file(&self, file: &gio::File) -> Result<(), String> {
+        self.load_file_with_encoding(file, None)?;
+        recent::add(file);
+        Ok(())
+    }
+
+    fn load_file_with_encoding(&self, file: &gio::File, encoding: Option<&'static Encoding>) -> Result<(), String> {
+        let text = self.borrow_mut().doc.open(file, encoding)?;
+        let buf = self.borrow().text_view.get_buffer().unwrap();
+        self.set_language(None);
+        self.borrow_mut().doc.undo.set_suspended(true);
+        buf.set_text(&text);
+        self.borrow_mut().doc.undo.set_suspended(false);
+        self.tab_label().set_text(file.get_basename().unwrap().to_str().unwrap());
+        self.set_changed(false);
+        self.detect_indent(&text);
+        self.detect_language();
+
+        Ok(())
+    }
+
+    fn reopen_with_encoding(&self, win: Window) {
+        let file = match self.file() {
+            Some(f) => f,
+            None => return,
+        };
+
+        if self.changed() {
+            let dialog = gtk::MessageDialog::new(Some(&win.win()),
+                                                 gtk::DIALOG_MODAL,
+                                                 gtk::MessageType::Warning,
+                                                 gtk::ButtonsType::None,
+                                                 "Discard changes and reopen the file?");
+            dialog.add_button("Cancel", ResponseType::Cancel.into());
+            dialog.add_button("Reopen", ResponseType::Accept.into());
+
+            let r = run_dialog(&win, &dialog);
+            dialog.destroy();
+
+            if r != ResponseType::Accept.into() {
+                return;
+            }
+        }
+
+        if let Some(enc) = self.encoding_chooser_run(win.clone(), "Reopen with Encoding") {
+            if let Err(e) = self.load_file_with_encoding(&file, Some(enc)) {
+                show_error(&win, &e);
+            }
         }
+    }
+
+    fn save_with_encoding(&self, wins: Windows, win: Window) -> bool {
+        if let Some(enc) = self.encoding_chooser_run(win.clone(), "Save with Encoding") {
+            self.set_encoding(enc);
+            return self.save_file(wins, win);
+        }
+
+        true
+    }
+
+    fn encoding_chooser_run(&self, win: Window, title: &str) -> Option<&'static Encoding> {
+        let dialog = gtk::Dialog::new();
+        dialog.set_title(title);
+        dialog.set_transient_for(&win.win());
+        dialog.set_modal(true);
+        dialog.add_button("Cancel", ResponseType::Cancel.into());
+        dialog.add_button("OK", ResponseType::Accept.into());
 
+        let combo = gtk::ComboBoxText::new();
+        for enc in encoding::encodings() {
+            combo.append(Some(enc.name()), enc.name());
+        }
+        combo.set_active_id(Some(self.encoding().name()));
+        dialog.get_content_area().add(&combo);
+        combo.show();
+
+        let enc;
+        if win.dialogs().run(&dialog) == ResponseType::Accept.into() {
+            enc = combo.get_active_id().and_then(|name| encoding::for_name(&name));
+        } else {
+            enc = None;
+        }
+
+        dialog.destroy();
+
+        enc
     }
 
     fn save_confirm(&self, wins: Windows, win: Window) -> bool {
-        if !self.changed() {
+        if !self.borrow().doc.needs_close_confirm() {
             return false;
         }
 
@@ -196,77 +690,113 @@ impl PageExtend for Page {
         dialog.add_button("Cancel", ResponseType::Cancel.into());
         dialog.add_button("Save", ResponseType::Accept.into());
 
-        let r = dialog.run();
+        let r = run_dialog(&win, &dialog);
         dialog.destroy();
 
-        if r == ResponseType::Accept.into() {
-            if self.file().is_some() {
-                self.save_file(wins.clone(), win)
-            } else {
-                self.save_as(wins.clone(), win)
-            }
-        } else if r == ResponseType::Cancel.into() {
-            true
+        let response = if r == ResponseType::Accept.into() {
+            Some(CloseResponse::Save)
         } else if r == ResponseType::Reject.into() {
-            false
+            Some(CloseResponse::Discard)
+        } else if r == ResponseType::Cancel.into() {
+            Some(CloseResponse::Cancel)
         } else {
-            true
+            None
+        };
+
+        let action = self.borrow().doc.close_action(response);
+        match action {
+            CloseAction::Close => false,
+            CloseAction::Keep => true,
+            CloseAction::Save => self.save_file(wins.clone(), win),
+            CloseAction::SaveAs => self.save_as(wins.clone(), win),
         }
     }
 
     fn save_file(&self, wins: Windows, win: Window) -> bool {
         if self.file().is_some() {
-            self.save_buffer(win.clone());
-            return false
+            !self.save_buffer(win.clone())
         } else {
             self.save_as(wins.clone(), win.clone())
         }
     }
 
-    fn save_buffer(&self, win: Window) {
-        if let Some(buf) = self.text_view().get_buffer() {
-            let (start, end) = buf.get_bounds();
-            if let Some(text) = buf.get_text(&start, &end, true) {
-                if self.file().as_ref().unwrap().replace_contents(text.as_bytes(),
-                                         None,
-                                         true,
-                                         gio::FILE_CREATE_NONE,
-                                         None).is_ok() {
-                    self.set_changed(false);
-                    self.tab_label().set_text(self.file().as_ref().unwrap().get_basename().unwrap().to_str().unwrap());
-                } else {
+    fn save_buffer(&self, win: Window) -> bool {
+        let file = self.file().unwrap();
+        let text = self.text();
+        let prefs = prefs::get();
+        let backup_dir = prefs.backup_dir();
+        let mut backup = match prefs.backup {
+            BackupMode::Timestamped => Some(backup_dir.as_path()),
+            _ => None,
+        };
+        let mut overwrite = false;
+
+        loop {
+            let saved = self.borrow_mut().doc.save(&file, &text, prefs.backup == BackupMode::Tilde, backup, overwrite);
+            match saved {
+                Ok(()) => {
+                    self.discard_snapshot();
+                    self.hide_info();
+                    self.tab_label().set_text(file.get_basename().unwrap().to_str().unwrap());
+                    return true;
+                },
+                Err(SaveError::Conflict) => {
                     let dialog = gtk::MessageDialog::new(Some(&win.win()),
                                                          gtk::DIALOG_MODAL,
-                                                         gtk::MessageType::Error,
-                                                         gtk::ButtonsType::Close,
-                                                         "Error: Cannot save file");
-                    dialog.run();
+                                                         gtk::MessageType::Warning,
+                                                         gtk::ButtonsType::None,
+                                                         "The file has been changed on disk since it was opened. Overwrite it?");
+                    dialog.add_button("Cancel", ResponseType::Cancel.into());
+                    dialog.add_button("Overwrite", ResponseType::Accept.into());
+
+                    let r = run_dialog(&win, &dialog);
                     dialog.destroy();
-                }
+
+                    if r != ResponseType::Accept.into() {
+                        return false;
+                    }
+                    overwrite = true;
+                    backup = None;
+                },
+                Err(e) => {
+                    show_error(&win, &e.to_string());
+                    return false;
+                },
             }
         }
     }
 
     fn save_as(&self, wins: Windows, win: Window) -> bool {
         if let Some(file) = self.save_file_chooser_run(win.clone()) {
-            if let Some(p) = wins.get_page(&file) {
-                if p.contents() != self.contents() {
-                    let dialog = gtk::MessageDialog::new(Some(&win.win()),
-                                                         gtk::DIALOG_MODAL,
-                                                         gtk::MessageType::Warning,
-                                                         gtk::ButtonsType::Close,
-                                                         "Error: Cannot save file because another window has been editing the file");
-                    dialog.run();
-                    dialog.destroy();
-                    
-                    return true;
-                }
+            let uri = file.get_uri().unwrap_or_default();
+            let registry = wins.registry();
+            let elsewhere = match wins.locate(self) {
+                Some((w, p)) => registry.is_open_elsewhere(w, p, &uri),
+                None => registry.find(&uri).is_some(),
+            };
+            if elsewhere {
+                let dialog = gtk::MessageDialog::new(Some(&win.win()),
+                                                     gtk::DIALOG_MODAL,
+                                                     gtk::MessageType::Warning,
+                                                     gtk::ButtonsType::Close,
+                                                     "Error: Cannot save file because another window has been editing the file");
+                run_dialog(&win, &dialog);
+                dialog.destroy();
+
+                return true;
             }
 
-            self.set_file(Some(file));
-            self.save_buffer(win);
+            let same_file = self.file().is_some_and(|f| f.equal(&file));
+            self.set_file(Some(file.clone()));
 
-            return false;
+            if self.save_buffer(win) {
+                recent::add(&file);
+                if !same_file {
+                    self.detect_language();
+                }
+                return false;
+            }
+            return true;
         }
 
         true
@@ -286,16 +816,7 @@ impl PageExtend for Page {
             dialog.set_current_name(Path::new("untitled"));
         }
 
-        let file;
-        if dialog.run() == ResponseType::Accept.into() {
-            if let Some(path) = dialog.get_filename() {
-                file = Some(gio::File::new_for_path(path.as_path()))
-            } else {
-                file = None
-            }
-        } else {
-            file = None
-        }
+        let file = win.dialogs().choose_file(&dialog).map(|path| gio::File::new_for_path(path.as_path()));
 
         dialog.destroy();
 
@@ -303,56 +824,1587 @@ impl PageExtend for Page {
     }
 
     fn show_warning(&self) {
-        self.borrow().revealer.set_reveal_child(true);
+        self.show_info("The file is being edited in another tab.", false);
     }
-}
 
+    fn show_info(&self, message: &str, actions: bool) {
+        let page = self.borrow();
+        page.info_label.set_text(message);
+        page.reload_button.set_visible(actions);
+        page.keep_button.set_visible(actions);
+        page.revealer.set_reveal_child(true);
+    }
 
-pub type Pages = Rc<RefCell<Vec<Page>>>;
+    fn hide_info(&self) {
+        self.borrow().revealer.set_reveal_child(false);
+    }
 
-pub trait PagesExtend {
-    fn create() -> Pages;
-    fn create_new_page(&self, wins: Windows, win: Window) -> Page;
-    fn len(&self) -> usize;
-    fn append(&self, page: Page);
-    fn get_page(&self, file: &gio::File) -> Option<Page>;
-    fn remove(&self, i: usize);
-}
+    fn check_file(&self) {
+        let file = match self.file() {
+            Some(f) => f,
+            None => return,
+        };
 
-impl PagesExtend for Pages {
-    fn create() -> Pages {
-        Rc::new(RefCell::new(Vec::<Page>::new()))
+        // A save, reload or another check may have finished while the query
+        // ran, in which case its answer is out of date.
+        let seen = self.borrow().doc.disk_etag().map(|e| e.to_string());
+        let p = self.clone();
+        query_etag(&file.clone(), move |disk_etag| {
+            if !p.file().is_some_and(|f| f.equal(&file)) || p.borrow().doc.disk_etag() != seen.as_deref() {
+                return;
+            }
+            let change = p.borrow_mut().doc.check_disk(disk_etag);
+            match change {
+                DiskChange::Unchanged => {},
+                DiskChange::Deleted => p.show_info("The file has been deleted or moved on disk.", false),
+                DiskChange::Restored => p.hide_info(),
+                DiskChange::Reload => p.reload(),
+                DiskChange::Conflict => p.show_info("The file has been changed on disk.", true),
+            }
+        });
     }
 
-    fn create_new_page(&self, wins: Windows, win: Window) -> Page {
-        let page = Page::create(wins.clone(), win);
-        self.append(page.clone());
+    fn autosave(&self) -> bool {
+        let file = match self.file() {
+            Some(f) => f,
+            None => return false,
+        };
+        let text = self.text();
+        let saved = self.borrow_mut().doc.save(&file, &text, false, None, false);
+        if saved.is_ok() {
+            self.discard_snapshot();
+        }
+        saved.is_ok()
+    }
 
-        page
+    fn snapshot_dirty(&self) -> bool {
+        self.borrow().snapshot_dirty
     }
 
-    fn len(&self) -> usize {
-        self.borrow().len()
+    fn set_snapshot_dirty(&self, dirty: bool) {
+        let mut page = self.borrow_mut();
+        page.snapshot_dirty = dirty;
+        if !dirty {
+            page.has_snapshot = true;
+        }
     }
 
-    fn append(&self, page: Page) {
-        self.borrow_mut().push(page);
+    fn recovery_entry(&self, window: usize) -> Entry {
+        let buf = self.text_view().get_buffer().unwrap();
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_offset();
+
+        Entry {
+            id: self.borrow().recovery_id.clone(),
+            uri: self.file().and_then(|f| f.get_uri()),
+            title: self.tab_label().get_text().unwrap_or_default(),
+            window: window,
+            cursor: cursor,
+            encoding: self.encoding().name().to_string(),
+            line_ending: self.line_ending().id().to_string(),
+        }
     }
 
-    fn get_page(&self, file: &gio::File) -> Option<Page> {
-        for p in self.borrow().deref() {
-            if let Some(ref f) = p.file() {
-                if f.equal(file) {
-                    return Some(p.clone());
-                }
+    fn discard_snapshot(&self) {
+        if !self.borrow().has_snapshot {
+            return;
+        }
+        recovery::remove(&self.borrow().recovery_id);
+        self.borrow_mut().has_snapshot = false;
+    }
+
+    fn restore(&self, entry: &Entry, text: &str) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        self.borrow_mut().doc.undo.set_suspended(true);
+        buf.set_text(text);
+        self.borrow_mut().doc.undo.set_suspended(false);
+        self.borrow_mut().doc.undo.clear();
+
+        if let Some(ref uri) = entry.uri {
+            let file = gio::File::new_for_uri(uri);
+            let disk_etag = file.current_etag();
+            self.set_file(Some(file));
+            self.borrow_mut().doc.set_disk_etag(disk_etag);
+        }
+        self.tab_label().set_text(&entry.title);
+
+        if let Some(enc) = encoding::for_name(&entry.encoding) {
+            self.set_encoding(enc);
+        }
+        self.set_line_ending(LineEnding::from_id(&entry.line_ending).unwrap_or_default());
+        self.set_changed(true);
+        self.borrow_mut().snapshot_dirty = true;
+        self.detect_language();
+
+        buf.place_cursor(&buf.get_iter_at_offset(entry.cursor));
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    fn view_position(&self) -> (i32, i32) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_offset();
+        let (top, _) = text_view.get_line_at_y(text_view.get_visible_rect().y);
+
+        (cursor, top.get_offset())
+    }
+
+    // The view is not laid out yet right after loading, so scroll through a
+    // mark, which GTK defers until the line heights are known.
+    fn set_view_position(&self, cursor: i32, top: i32) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        buf.place_cursor(&buf.get_iter_at_offset(cursor));
+
+        let iter = buf.get_iter_at_offset(top);
+        let mark = match buf.get_mark("view-top") {
+            Some(mark) => {
+                buf.move_mark(&mark, &iter);
+                mark
+            },
+            None => buf.create_mark("view-top", &iter, true).unwrap(),
+        };
+        text_view.scroll_to_mark(&mark, 0.0, true, 0.0, 0.0);
+    }
+
+    fn reload(&self) {
+        let file = match self.file() {
+            Some(f) => f,
+            None => return,
+        };
+
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let offset = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_offset();
+
+        let encoding = self.encoding();
+        if self.load_file_with_encoding(&file, Some(encoding)).is_ok() {
+            buf.place_cursor(&buf.get_iter_at_offset(offset));
+            text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+            self.hide_info();
+        }
+    }
+
+    fn undo(&self) {
+        let edits = self.borrow_mut().doc.undo.undo();
+        if let Some(edits) = edits {
+            self.apply_edits(edits);
+            self.borrow_mut().doc.update_modified();
+        }
+    }
+
+    fn redo(&self) {
+        let edits = self.borrow_mut().doc.undo.redo();
+        if let Some(edits) = edits {
+            self.apply_edits(edits);
+            self.borrow_mut().doc.update_modified();
+        }
+    }
+
+    fn apply_edits(&self, edits: Vec<Edit>) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let mut cursor = None;
+
+        self.borrow_mut().doc.undo.set_suspended(true);
+        for edit in edits {
+            match edit {
+                Edit::Insert { offset, text } => {
+                    let mut iter = buf.get_iter_at_offset(offset as i32);
+                    buf.insert(&mut iter, &text);
+                    cursor = Some(iter.get_offset());
+                },
+                Edit::Delete { offset, text } => {
+                    let mut start = buf.get_iter_at_offset(offset as i32);
+                    let mut end = buf.get_iter_at_offset((offset + text.chars().count()) as i32);
+                    buf.delete(&mut start, &mut end);
+                    cursor = Some(offset as i32);
+                },
             }
         }
-        
-        None
+        self.borrow_mut().doc.undo.set_suspended(false);
+
+        if let Some(offset) = cursor {
+            buf.place_cursor(&buf.get_iter_at_offset(offset));
+            text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+        }
     }
 
-    fn remove(&self, i: usize) {
-        self.borrow_mut().remove(i);
+    fn show_find_bar(&self, replace: bool) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let text = buf.get_selection_bounds().and_then(|(start, end)| {
+            if start.get_line() == end.get_line() {
+                buf.get_text(&start, &end, false)
+            } else {
+                None
+            }
+        });
+
+        self.borrow().find_bar.show(replace, text);
+        self.update_matches();
     }
-}
 
+    fn hide_find_bar(&self) {
+        self.borrow().find_bar.hide();
+
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (start, end) = buf.get_bounds();
+        buf.remove_tag_by_name("search-match", &start, &end);
+        text_view.grab_focus();
+    }
+
+    fn search_matches(&self) -> Option<Vec<Match>> {
+        let query = self.borrow().find_bar.query();
+        if query.is_empty() {
+            return None;
+        }
+
+        if let Ok(matcher) = query.matcher() {
+            Some(matcher.find_all(&self.text()))
+        } else {
+            None
+        }
+    }
+
+    fn update_matches(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let (start, end) = buf.get_bounds();
+        buf.remove_tag_by_name("search-match", &start, &end);
+
+        let query = self.borrow().find_bar.query();
+        if query.is_empty() {
+            self.borrow().find_bar.set_status("", false);
+            return;
+        }
+        if query.matcher().is_err() {
+            self.borrow().find_bar.set_status("Invalid pattern", true);
+            return;
+        }
+
+        let matches = self.search_matches().unwrap_or_default();
+        for m in &matches {
+            let start = buf.get_iter_at_offset(m.start as i32);
+            let end = buf.get_iter_at_offset(m.end as i32);
+            buf.apply_tag_by_name("search-match", &start, &end);
+        }
+
+        let status = match matches.len() {
+            0 => "No matches".to_string(),
+            1 => "1 match".to_string(),
+            n => format!("{} matches", n),
+        };
+        self.borrow().find_bar.set_status(&status, false);
+    }
+
+    fn queue_update_matches(&self) {
+        if self.borrow().find_pending {
+            return;
+        }
+        self.borrow_mut().find_pending = true;
+
+        let p = self.clone();
+        gtk::idle_add(move || {
+            p.borrow_mut().find_pending = false;
+            if p.borrow().find_bar.is_visible() {
+                p.update_matches();
+            }
+            Continue(false)
+        });
+    }
+
+    fn find_next(&self, forward: bool) {
+        let matches = match self.search_matches() {
+            Some(ref m) if !m.is_empty() => m.clone(),
+            _ => return,
+        };
+
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (sel_start, sel_end) = match buf.get_selection_bounds() {
+            Some((start, end)) => (start.get_offset() as usize, end.get_offset() as usize),
+            None => {
+                let o = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_offset() as usize;
+                (o, o)
+            },
+        };
+
+        let m = if forward {
+            matches.iter().find(|m| m.start >= sel_end).unwrap_or(&matches[0])
+        } else {
+            matches.iter().rev().find(|m| m.end <= sel_start).unwrap_or(&matches[matches.len() - 1])
+        };
+
+        self.select_offsets(m.start, m.end);
+    }
+
+    fn replace_match(&self) {
+        let query = self.borrow().find_bar.query();
+        let replacement = self.borrow().find_bar.replacement();
+        let matcher = match query.matcher() {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+
+        let buf = self.text_view().get_buffer().unwrap();
+        if let Some((start, end)) = buf.get_selection_bounds() {
+            let (all_start, all_end) = buf.get_bounds();
+            let text = buf.get_text(&all_start, &all_end, true).unwrap_or_default();
+            let (s, e) = (start.get_offset() as usize, end.get_offset() as usize);
+
+            let found = matcher.replace_all(&text, &replacement).into_iter().find(|&(m, _)| {
+                m.start == s && m.end == e
+            });
+            if let Some((_, rep)) = found {
+                let (mut start, mut end) = (start, end);
+                buf.begin_user_action();
+                buf.delete(&mut start, &mut end);
+                buf.insert(&mut start, &rep);
+                buf.end_user_action();
+            }
+        }
+
+        self.find_next(true);
+    }
+
+    fn replace_all_matches(&self) {
+        let query = self.borrow().find_bar.query();
+        let replacement = self.borrow().find_bar.replacement();
+        let matcher = match query.matcher() {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+
+        let buf = self.text_view().get_buffer().unwrap();
+        let (start, end) = buf.get_bounds();
+        let text = buf.get_text(&start, &end, true).unwrap_or_default();
+        let replacements = matcher.replace_all(&text, &replacement);
+        if replacements.is_empty() {
+            return;
+        }
+
+        buf.begin_user_action();
+        for &(m, ref rep) in replacements.iter().rev() {
+            let mut start = buf.get_iter_at_offset(m.start as i32);
+            let mut end = buf.get_iter_at_offset(m.end as i32);
+            buf.delete(&mut start, &mut end);
+            buf.insert(&mut start, rep);
+        }
+        buf.end_user_action();
+    }
+
+    fn set_language(&self, language: Option<Rc<Language>>) {
+        let buf = self.text_view().get_buffer().unwrap();
+        highlight::clear(&buf);
+        self.borrow_mut().highlighter = language.map(|lang| {
+            Highlighter::new(lang, buf.get_line_count() as usize)
+        });
+        let auto_close = prefs::get().auto_close_for(self.language().as_ref().map(|l| l.id.as_str()));
+        self.borrow_mut().auto_close = auto_close;
+        self.queue_highlight();
+        self.queue_update_brackets();
+    }
+
+    fn detect_language(&self) {
+        let name = self.file().and_then(|f| f.get_basename());
+        let name = name.as_ref().and_then(|n| n.to_str());
+
+        let buf = self.text_view().get_buffer().unwrap();
+        let start = buf.get_start_iter();
+        let mut end = start.clone();
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        let first_line = start.get_slice(&end).unwrap_or_default();
+
+        self.set_language(syntax::detect(name, &first_line));
+    }
+
+    fn queue_highlight(&self) {
+        if self.borrow().highlight_pending || self.borrow().highlighter.is_none() {
+            return;
+        }
+        self.borrow_mut().highlight_pending = true;
+
+        let p = self.clone();
+        gtk::idle_add(move || {
+            let h = p.borrow_mut().highlighter.take();
+            let more = match h {
+                Some(mut h) => {
+                    let buf = p.text_view().get_buffer().unwrap();
+                    let more = highlight::highlight_lines(&buf, &mut h, highlight::BATCH_LINES);
+                    p.borrow_mut().highlighter = Some(h);
+                    more
+                },
+                None => false,
+            };
+            if !more {
+                p.borrow_mut().highlight_pending = false;
+            }
+            Continue(more)
+        });
+    }
+
+    fn set_line_numbers(&self, visible: bool) {
+        self.borrow().gutter.set_visible(visible);
+    }
+
+    fn apply_prefs(&self, prefs: &Preferences) {
+        let text_view = self.text_view();
+        text_view.override_font(&pango::FontDescription::from_string(&prefs.font));
+        text_view.set_wrap_mode(if prefs.wrap_lines { gtk::WrapMode::WordChar } else { gtk::WrapMode::None });
+
+        self.set_line_numbers(prefs.line_numbers);
+        // A style detected in the file wins over the preferences.
+        let detected = self.borrow().detected_indent;
+        self.set_indent_width(detected.and_then(|d| d.width).unwrap_or(prefs.tab_width));
+        self.set_indent_spaces(detected.map_or(prefs.insert_spaces, |d| d.spaces));
+        self.borrow_mut().highlight_line = prefs.highlight_current_line;
+        self.update_current_line();
+        let auto_close = prefs.auto_close_for(self.language().as_ref().map(|l| l.id.as_str()));
+        self.borrow_mut().auto_close = auto_close;
+    }
+
+    // Only the line highlighted last time needs clearing. The marks around
+    // it take in text inserted into it, such as lines broken off it.
+    fn update_current_line(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        if let (Some(start), Some(end)) = (buf.get_mark("current-line-start"), buf.get_mark("current-line-end")) {
+            buf.remove_tag_by_name("current-line", &buf.get_iter_at_mark(&start), &buf.get_iter_at_mark(&end));
+        }
+        if !self.borrow().highlight_line {
+            return;
+        }
+
+        let start = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let start = buf.get_iter_at_line(start.get_line());
+        let mut end = start.clone();
+        end.forward_line();
+        buf.apply_tag_by_name("current-line", &start, &end);
+
+        for &(name, iter, left) in &[("current-line-start", &start, true), ("current-line-end", &end, false)] {
+            match buf.get_mark(name) {
+                Some(mark) => buf.move_mark(&mark, iter),
+                None => {
+                    buf.create_mark(name, iter, left);
+                },
+            }
+        }
+    }
+
+    fn language(&self) -> Option<Rc<Language>> {
+        self.borrow().highlighter.as_ref().map(|h| h.language().clone())
+    }
+
+    fn indent_width(&self) -> u32 {
+        self.borrow().indent_width
+    }
+
+    fn set_indent_width(&self, width: u32) {
+        self.borrow_mut().indent_width = width;
+
+        let text_view = self.text_view();
+        if let Some(layout) = text_view.create_pango_layout(" ") {
+            let mut tabs = pango::TabArray::new(1, true);
+            tabs.set_tab(0, pango::TabAlign::Left, layout.get_pixel_size().0 * width as i32);
+            text_view.set_tabs(&mut tabs);
+        }
+    }
+
+    fn indent_spaces(&self) -> bool {
+        self.borrow().indent_spaces
+    }
+
+    fn set_indent_spaces(&self, spaces: bool) {
+        self.borrow_mut().indent_spaces = spaces;
+    }
+
+    // Inserts spaces up to the next tab stop instead of a tab character.
+    fn insert_tab(&self) -> bool {
+        if !self.indent_spaces() {
+            return false;
+        }
+        let buf = self.text_view().get_buffer().unwrap();
+        if buf.get_has_selection() {
+            return false;
+        }
+
+        let mut iter = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let start = buf.get_iter_at_line(iter.get_line());
+        let width = self.indent_width();
+        let column = indent::columns(&start.get_slice(&iter).unwrap_or_default(), width);
+
+        buf.begin_user_action();
+        buf.insert(&mut iter, &indent::tab(column, width, true));
+        buf.end_user_action();
+        true
+    }
+
+    // Starts the new line at the indentation of the current one, a level
+    // deeper after a line that opens a block in the page's language.
+    fn insert_newline(&self) -> bool {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let openers = self.language().map_or(Vec::new(), |l| l.indent_after.clone());
+
+        buf.begin_user_action();
+        buf.delete_selection(true, text_view.get_editable());
+        let iter = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let start = buf.get_iter_at_line(iter.get_line());
+        let before = start.get_slice(&iter).unwrap_or_default();
+        let indent = indent::next_line(&before, &openers, self.indent_width(), self.indent_spaces());
+        buf.insert_interactive_at_cursor(&format!("\n{}", indent), text_view.get_editable());
+        buf.end_user_action();
+
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+        true
+    }
+
+    // Indents or unindents every line touched by the selection. Indenting
+    // needs a selection over more than one line; unindenting also works on
+    // the line with the cursor.
+    fn indent_lines(&self, deeper: bool) -> bool {
+        let buf = self.text_view().get_buffer().unwrap();
+        let (start, end) = match buf.get_selection_bounds() {
+            Some(bounds) => bounds,
+            None => {
+                let iter = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+                (iter.clone(), iter)
+            },
+        };
+        let first = start.get_line();
+        let mut last = end.get_line();
+        if last > first && end.starts_line() {
+            last -= 1;
+        }
+        if deeper && first == last {
+            return false;
+        }
+
+        let width = self.indent_width();
+        let unit = indent::unit(width, self.indent_spaces());
+        buf.begin_user_action();
+        for line in first..last + 1 {
+            let mut line_start = buf.get_iter_at_line(line);
+            let mut line_end = line_start.clone();
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            let text = line_start.get_slice(&line_end).unwrap_or_default();
+            if deeper {
+                if !text.trim().is_empty() {
+                    buf.insert(&mut line_start, &unit);
+                }
+            } else {
+                let n = indent::unindent_len(&text, width);
+                if n > 0 {
+                    let mut indent_end = line_start.clone();
+                    indent_end.forward_chars(n as i32);
+                    buf.delete(&mut line_start, &mut indent_end);
+                }
+            }
+        }
+        buf.end_user_action();
+
+        if start != end {
+            let start = buf.get_iter_at_line(first);
+            let mut end = buf.get_iter_at_line(last);
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            buf.select_range(&start, &end);
+        }
+        true
+    }
+
+    // Applies `op` to the lines touched by the selection, or the line with
+    // the cursor, as one undoable step.
+    fn edit_lines(&self, op: LineOp) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (start, end, selected) = match buf.get_selection_bounds() {
+            Some((start, end)) => (start, end, true),
+            None => {
+                let iter = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+                (iter.clone(), iter, false)
+            },
+        };
+        let column = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_line_offset();
+        let lines = op.lines(start.get_line() as usize, end.get_line() as usize, end.starts_line(),
+                             buf.get_line_count() as usize);
+        let (first, last) = match lines {
+            Some((first, last)) => (first as i32, last as i32),
+            None => return,
+        };
+
+        let mut block_start = buf.get_iter_at_line(first);
+        let mut block_end = buf.get_iter_at_line(last);
+        if !block_end.ends_line() {
+            block_end.forward_to_line_end();
+        }
+        let text = block_start.get_slice(&block_end).unwrap_or_default();
+        let block: Vec<&str> = text.split('\n').collect();
+        let edit = op.apply(&block);
+        if edit.lines.iter().map(|l| l.as_str()).eq(block.iter().cloned()) {
+            return;
+        }
+
+        // A removed block takes its line break along, or the one before it
+        // at the end of the buffer.
+        if edit.lines.is_empty() && !block_end.forward_line() && first > 0 {
+            block_start.backward_char();
+        }
+
+        buf.begin_user_action();
+        buf.delete(&mut block_start, &mut block_end);
+        buf.insert(&mut block_start, &edit.lines.join("\n"));
+        buf.end_user_action();
+
+        let sel_first = cmp::min(first + edit.select.0 as i32, buf.get_line_count() - 1);
+        let sel_last = cmp::min(first + edit.select.1 as i32, buf.get_line_count() - 1);
+        if selected && !edit.lines.is_empty() {
+            let start = buf.get_iter_at_line(sel_first);
+            let mut end = buf.get_iter_at_line(sel_last);
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            buf.select_range(&start, &end);
+        } else {
+            let mut iter = buf.get_iter_at_line(sel_first);
+            let mut line_end = iter.clone();
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            iter.set_line_offset(cmp::min(column, line_end.get_line_offset()));
+            buf.place_cursor(&iter);
+        }
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    fn block(&self) -> Option<Block> {
+        self.borrow().block
+    }
+
+    // Shows the block and moves the cursor to its head, or drops it.
+    fn set_block(&self, block: Option<Block>) {
+        self.borrow_mut().block = block;
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (start, end) = buf.get_bounds();
+        buf.remove_tag_by_name("block-selection", &start, &end);
+        let block = match block {
+            Some(block) => block,
+            None => return,
+        };
+
+        let tab = self.indent_width();
+        let (first, last) = block.lines();
+        let (left, right) = block.columns();
+        for line in first..last + 1 {
+            let (start, end) = line_bounds(&buf, line as i32);
+            let text = start.get_slice(&end).unwrap_or_default();
+            let mut from = start.clone();
+            from.set_line_offset(block::index_at(&text, left, tab) as i32);
+            let mut to = start.clone();
+            to.set_line_offset(block::index_at(&text, right, tab) as i32);
+            buf.apply_tag_by_name("block-selection", &from, &to);
+        }
+
+        let (start, end) = line_bounds(&buf, block.head.0 as i32);
+        let text = start.get_slice(&end).unwrap_or_default();
+        let mut iter = start.clone();
+        iter.set_line_offset(block::index_at(&text, block.head.1, tab) as i32);
+        self.borrow_mut().block_busy = true;
+        buf.place_cursor(&iter);
+        self.borrow_mut().block_busy = false;
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    // The line and display column of the cursor.
+    fn cursor_point(&self) -> (usize, usize) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let iter = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let start = buf.get_iter_at_line(iter.get_line());
+        let before = start.get_slice(&iter).unwrap_or_default();
+        (iter.get_line() as usize, block::column_at(&before, before.chars().count(), self.indent_width()))
+    }
+
+    // Alt+Shift+arrows start or stretch a block. While one is shown, typing
+    // goes onto every line of it and Backspace and Delete work on its
+    // columns; other keys take their usual effect, which drops the block.
+    fn block_key(&self, event: &gdk::EventKey) -> bool {
+        let keyval = event.get_keyval();
+        let modifiers = event.get_state() & (gdk::SHIFT_MASK | gdk::CONTROL_MASK | gdk::MOD1_MASK);
+
+        if modifiers == gdk::SHIFT_MASK | gdk::MOD1_MASK {
+            let step = match keyval {
+                gdk::enums::key::Up => Some((-1, 0)),
+                gdk::enums::key::Down => Some((1, 0)),
+                gdk::enums::key::Left => Some((0, -1)),
+                gdk::enums::key::Right => Some((0, 1)),
+                _ => None,
+            };
+            if let Some((lines, columns)) = step {
+                let block = self.block().unwrap_or_else(|| {
+                    let point = self.cursor_point();
+                    Block::new(point, point)
+                });
+                let last = self.text_view().get_buffer().unwrap().get_line_count() - 1;
+                let line = cmp::min(cmp::max(block.head.0 as i32 + lines, 0), last);
+                let column = cmp::max(block.head.1 as i32 + columns, 0);
+                self.set_block(Some(Block::new(block.anchor, (line as usize, column as usize))));
+                return true;
+            }
+        }
+
+        let block = match self.block() {
+            Some(block) => block,
+            None => return false,
+        };
+        let (left, _) = block.columns();
+        match keyval {
+            gdk::enums::key::Escape if modifiers.is_empty() => {
+                self.set_block(None);
+                true
+            },
+            gdk::enums::key::BackSpace | gdk::enums::key::Delete if modifiers.is_empty() => {
+                // Without a width the column before or after the block goes.
+                if block.is_empty() {
+                    let (from, to) = match keyval {
+                        gdk::enums::key::BackSpace if left == 0 => return true,
+                        gdk::enums::key::BackSpace => (left - 1, left),
+                        _ => (left, left + 1),
+                    };
+                    self.borrow_mut().block = Some(Block::new((block.anchor.0, from), (block.head.0, to)));
+                }
+                self.replace_block(&[]);
+                true
+            },
+            _ => match gdk::keyval_to_unicode(keyval) {
+                Some(c) if !c.is_control() && (modifiers & (gdk::CONTROL_MASK | gdk::MOD1_MASK)).is_empty() => {
+                    self.replace_block(&[c.to_string()]);
+                    true
+                },
+                _ => false,
+            },
+        }
+    }
+
+    fn block_text(&self) -> Option<String> {
+        let block = self.block()?;
+        let buf = self.text_view().get_buffer().unwrap();
+        let (first, last) = block.lines();
+        let (left, right) = block.columns();
+        let start = buf.get_iter_at_line(first as i32);
+        let (_, end) = line_bounds(&buf, last as i32);
+        let text = start.get_slice(&end).unwrap_or_default();
+        let lines: Vec<&str> = text.split('\n').collect();
+        Some(block::extract(&lines, left, right, self.indent_width()).join("\n"))
+    }
+
+    // Puts `text` in place of the block's columns as one undoable step, and
+    // leaves a block without width after it, spanning any lines pasted.
+    fn replace_block(&self, text: &[String]) {
+        let block = match self.block() {
+            Some(block) => block,
+            None => return,
+        };
+        let text_view = self.text_view();
+        if !text_view.get_editable() {
+            return;
+        }
+        let buf = text_view.get_buffer().unwrap();
+        let tab = self.indent_width();
+        let (first, last) = block.lines();
+        let (left, right) = block.columns();
+        let height = cmp::max(last - first + 1, text.len());
+
+        let mut start = buf.get_iter_at_line(first as i32);
+        let end_line = cmp::min(first + height, buf.get_line_count() as usize) - 1;
+        let (_, mut end) = line_bounds(&buf, end_line as i32);
+        let old = start.get_slice(&end).unwrap_or_default();
+        let lines: Vec<&str> = old.split('\n').collect();
+        let new = block::replace(&lines, left, right, text, tab).join("\n");
+
+        if new != old {
+            self.borrow_mut().block_busy = true;
+            buf.begin_user_action();
+            buf.delete(&mut start, &mut end);
+            buf.insert(&mut start, &new);
+            buf.end_user_action();
+            self.borrow_mut().block_busy = false;
+        }
+
+        let column = left + block::width(text, tab);
+        if height > last - first + 1 {
+            self.set_block(Some(Block::new((first, column), (first + height - 1, column))));
+        } else {
+            self.set_block(Some(block.at_column(column)));
+        }
+    }
+
+    fn copy_block(&self, clipboard: &gtk::Clipboard) -> bool {
+        let text = match self.block_text() {
+            Some(text) => text,
+            None => return false,
+        };
+        if self.block().is_some_and(|b| !b.is_empty()) {
+            clipboard.set_text(&text);
+            BLOCK_CLIPBOARD.with(|b| *b.borrow_mut() = Some(text));
+        }
+        true
+    }
+
+    fn cut_block(&self, clipboard: &gtk::Clipboard) -> bool {
+        if !self.copy_block(clipboard) {
+            return false;
+        }
+        self.replace_block(&[]);
+        true
+    }
+
+    // Text copied from a block is pasted as a block, at the cursor or over
+    // the block shown. Other text is pasted into a block when it has a
+    // single line, which goes onto every line, or one line for each.
+    fn paste_block(&self, clipboard: &gtk::Clipboard) -> bool {
+        let text = match clipboard.wait_for_text() {
+            Some(text) => text,
+            None => return false,
+        };
+        let copied = BLOCK_CLIPBOARD.with(|b| b.borrow().as_ref() == Some(&text));
+        let entries: Vec<String> = text.split('\n').map(|l| l.trim_end_matches('\r').to_string()).collect();
+        let has_selection = self.text_view().get_buffer().unwrap().get_has_selection();
+
+        let block = match self.block() {
+            Some(block) => {
+                let (first, last) = block.lines();
+                if !copied && entries.len() > 1 && entries.len() != last - first + 1 {
+                    self.set_block(None);
+                    return false;
+                }
+                block
+            },
+            None if copied && !has_selection => {
+                let point = self.cursor_point();
+                Block::new(point, point)
+            },
+            None => return false,
+        };
+        self.borrow_mut().block = Some(block);
+        self.replace_block(&entries);
+        true
+    }
+
+    // The cursor and every extra caret, as their head and selection bound.
+    fn caret_marks(&self) -> Vec<(gtk::TextMark, gtk::TextMark)> {
+        let buf = self.text_view().get_buffer().unwrap();
+        let mut marks = vec![(buf.get_insert().unwrap(), buf.get_selection_bound().unwrap())];
+        marks.extend(self.borrow().carets.iter().cloned());
+        marks
+    }
+
+    fn has_carets(&self) -> bool {
+        !self.borrow().carets.is_empty()
+    }
+
+    fn add_caret(&self, head: &gtk::TextIter, anchor: &gtk::TextIter) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let head = buf.create_mark(None, head, false).unwrap();
+        let anchor = buf.create_mark(None, anchor, false).unwrap();
+        self.borrow_mut().carets.push((head, anchor));
+    }
+
+    // Ctrl+click leaves a caret where the cursor was and moves the cursor,
+    // or removes the extra caret clicked on.
+    fn click_caret(&self, iter: &gtk::TextIter) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let clicked = self.borrow().carets.iter()
+            .position(|&(ref head, _)| buf.get_iter_at_mark(head) == *iter);
+        if let Some(i) = clicked {
+            let (head, anchor) = self.borrow_mut().carets.remove(i);
+            buf.delete_mark(&head);
+            buf.delete_mark(&anchor);
+        } else {
+            let head = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+            let anchor = buf.get_iter_at_mark(&buf.get_selection_bound().unwrap());
+            if head == *iter {
+                return;
+            }
+            self.add_caret(&head, &anchor);
+            self.borrow_mut().carets_busy = true;
+            buf.place_cursor(iter);
+            self.borrow_mut().carets_busy = false;
+        }
+        self.update_carets();
+    }
+
+    fn clear_carets(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let marks: Vec<_> = self.borrow_mut().carets.drain(..).collect();
+        for (head, anchor) in marks {
+            buf.delete_mark(&head);
+            buf.delete_mark(&anchor);
+        }
+        self.update_carets();
+    }
+
+    // Drops carets that ran into another and redraws them all.
+    fn update_carets(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let ranges: Vec<(usize, usize)> = self.caret_marks().iter().map(|&(ref head, ref anchor)| {
+            let (a, b) = (buf.get_iter_at_mark(head).get_offset(), buf.get_iter_at_mark(anchor).get_offset());
+            (cmp::min(a, b) as usize, cmp::max(a, b) as usize)
+        }).collect();
+        for i in carets::overlapping(&ranges).into_iter().rev() {
+            let (head, anchor) = self.borrow_mut().carets.remove(i - 1);
+            buf.delete_mark(&head);
+            buf.delete_mark(&anchor);
+        }
+
+        let (start, end) = buf.get_bounds();
+        buf.remove_tag_by_name("caret-selection", &start, &end);
+        for &(ref head, ref anchor) in &self.borrow().carets {
+            buf.apply_tag_by_name("caret-selection", &buf.get_iter_at_mark(head), &buf.get_iter_at_mark(anchor));
+        }
+        self.borrow().caret_area.queue_draw();
+    }
+
+    // Selects the word at the cursor, or adds a caret selecting the next
+    // occurrence of the selected text and moves the cursor there.
+    fn add_next_occurrence(&self) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (start, end) = match buf.get_selection_bounds() {
+            Some(bounds) => bounds,
+            None => {
+                let mut start = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+                if !start.inside_word() && !start.ends_word() {
+                    return;
+                }
+                if !start.starts_word() {
+                    start.backward_word_start();
+                }
+                let mut end = start.clone();
+                end.forward_word_end();
+                buf.select_range(&end, &start);
+                return;
+            },
+        };
+
+        let needle = start.get_slice(&end).unwrap_or_default();
+        let ranges: Vec<(usize, usize)> = self.caret_marks().iter().map(|&(ref head, ref anchor)| {
+            let (a, b) = (buf.get_iter_at_mark(head).get_offset(), buf.get_iter_at_mark(anchor).get_offset());
+            (cmp::min(a, b) as usize, cmp::max(a, b) as usize)
+        }).collect();
+        let from = end.get_offset() as usize;
+        let found = match carets::next_occurrence(&self.text(), &needle, from, &ranges) {
+            Some(found) => found,
+            None => return,
+        };
+
+        let head = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let anchor = buf.get_iter_at_mark(&buf.get_selection_bound().unwrap());
+        self.add_caret(&head, &anchor);
+        let start = buf.get_iter_at_offset(found as i32);
+        let end = buf.get_iter_at_offset((found + needle.chars().count()) as i32);
+        self.borrow_mut().carets_busy = true;
+        buf.select_range(&end, &start);
+        self.borrow_mut().carets_busy = false;
+        self.update_carets();
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    // Puts a caret at the end of every line touched by the selection.
+    fn add_carets_to_lines(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let (start, end) = match buf.get_selection_bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let first = start.get_line();
+        let mut last = end.get_line();
+        if last > first && end.starts_line() {
+            last -= 1;
+        }
+
+        for line in first..last {
+            let (_, end) = line_bounds(&buf, line);
+            self.add_caret(&end, &end);
+        }
+        let (_, end) = line_bounds(&buf, last);
+        self.borrow_mut().carets_busy = true;
+        buf.place_cursor(&end);
+        self.borrow_mut().carets_busy = false;
+        self.update_carets();
+    }
+
+    // With extra carets, typing, deleting and moving act on all of them and
+    // Escape leaves only the cursor.
+    fn carets_key(&self, event: &gdk::EventKey) -> bool {
+        if !self.has_carets() {
+            return false;
+        }
+        let keyval = event.get_keyval();
+        let modifiers = event.get_state() & (gdk::SHIFT_MASK | gdk::CONTROL_MASK | gdk::MOD1_MASK);
+        let width = self.indent_width();
+        let spaces = self.indent_spaces();
+
+        match keyval {
+            gdk::enums::key::Escape if modifiers.is_empty() => self.clear_carets(),
+            gdk::enums::key::BackSpace | gdk::enums::key::Delete if modifiers.is_empty() => {
+                self.edit_carets(|buf, _, start, end| {
+                    if start == end {
+                        if keyval == gdk::enums::key::BackSpace {
+                            start.backward_cursor_position();
+                        } else {
+                            end.forward_cursor_position();
+                        }
+                    }
+                    buf.delete(start, end);
+                });
+            },
+            gdk::enums::key::Return | gdk::enums::key::KP_Enter if modifiers.is_empty() => {
+                let openers = self.language().map_or(Vec::new(), |l| l.indent_after.clone());
+                self.edit_carets(|buf, _, start, end| {
+                    buf.delete(start, end);
+                    let line_start = buf.get_iter_at_line(start.get_line());
+                    let before = line_start.get_slice(start).unwrap_or_default();
+                    let indent = indent::next_line(&before, &openers, width, spaces);
+                    buf.insert(start, &format!("\n{}", indent));
+                });
+            },
+            gdk::enums::key::Tab if modifiers.is_empty() => {
+                self.edit_carets(|buf, _, start, end| {
+                    buf.delete(start, end);
+                    let line_start = buf.get_iter_at_line(start.get_line());
+                    let column = indent::columns(&line_start.get_slice(start).unwrap_or_default(), width);
+                    buf.insert(start, &indent::tab(column, width, spaces));
+                });
+            },
+            gdk::enums::key::Left | gdk::enums::key::Right | gdk::enums::key::Up | gdk::enums::key::Down |
+            gdk::enums::key::Home | gdk::enums::key::End
+                if modifiers.is_empty() || modifiers == gdk::SHIFT_MASK => {
+                self.move_carets(keyval, !modifiers.is_empty());
+            },
+            _ => match gdk::keyval_to_unicode(keyval) {
+                Some(c) if !c.is_control() && (modifiers & (gdk::CONTROL_MASK | gdk::MOD1_MASK)).is_empty() => {
+                    let typed = c.to_string();
+                    self.edit_carets(|buf, _, start, end| {
+                        buf.delete(start, end);
+                        buf.insert(start, &typed);
+                    });
+                },
+                _ => return false,
+            },
+        }
+        true
+    }
+
+    // Runs `edit` on the selection of every caret, the cursor's first, as
+    // one undoable step. It leaves `start` where the caret goes.
+    fn edit_carets<F: Fn(&gtk::TextBuffer, usize, &mut gtk::TextIter, &mut gtk::TextIter)>(&self, edit: F) {
+        let text_view = self.text_view();
+        if !text_view.get_editable() {
+            return;
+        }
+        let buf = text_view.get_buffer().unwrap();
+
+        self.borrow_mut().carets_busy = true;
+        buf.begin_user_action();
+        for (i, (head, anchor)) in self.caret_marks().into_iter().enumerate() {
+            let a = buf.get_iter_at_mark(&head);
+            let b = buf.get_iter_at_mark(&anchor);
+            let (mut start, mut end) = if a <= b { (a, b) } else { (b, a) };
+            edit(&buf, i, &mut start, &mut end);
+            buf.move_mark(&head, &start);
+            buf.move_mark(&anchor, &start);
+        }
+        buf.end_user_action();
+        self.borrow_mut().carets_busy = false;
+
+        self.update_carets();
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    // Moves every caret by a character, a line or to either end of its
+    // line, keeping the other end of its selection when extending.
+    fn move_carets(&self, keyval: u32, extend: bool) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+
+        self.borrow_mut().carets_busy = true;
+        for (head, anchor) in self.caret_marks() {
+            let mut iter = buf.get_iter_at_mark(&head);
+            let bound = buf.get_iter_at_mark(&anchor);
+            let collapse = !extend && iter != bound;
+            match keyval {
+                gdk::enums::key::Left if collapse => iter = cmp::min(iter, bound.clone()),
+                gdk::enums::key::Right if collapse => iter = cmp::max(iter, bound.clone()),
+                gdk::enums::key::Left => {
+                    iter.backward_cursor_position();
+                },
+                gdk::enums::key::Right => {
+                    iter.forward_cursor_position();
+                },
+                gdk::enums::key::Up | gdk::enums::key::Down => {
+                    let line = iter.get_line() + if keyval == gdk::enums::key::Up { -1 } else { 1 };
+                    if line >= 0 && line < buf.get_line_count() {
+                        let offset = iter.get_line_offset();
+                        let (start, end) = line_bounds(&buf, line);
+                        iter = start;
+                        iter.set_line_offset(cmp::min(offset, end.get_line_offset()));
+                    }
+                },
+                gdk::enums::key::Home => iter.set_line_offset(0),
+                _ => {
+                    if !iter.ends_line() {
+                        iter.forward_to_line_end();
+                    }
+                },
+            }
+            buf.move_mark(&head, &iter);
+            if !extend {
+                buf.move_mark(&anchor, &iter);
+            }
+        }
+        self.borrow_mut().carets_busy = false;
+
+        self.update_carets();
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    fn paste_carets(&self, clipboard: &gtk::Clipboard) -> bool {
+        if !self.has_carets() {
+            return false;
+        }
+        let text = match clipboard.wait_for_text() {
+            Some(text) => text,
+            None => return true,
+        };
+        let texts = carets::paste_text(&text, self.caret_marks().len());
+        self.edit_carets(|buf, i, start, end| {
+            buf.delete(start, end);
+            buf.insert(start, &texts[i]);
+        });
+        true
+    }
+
+    // The bracket after the cursor, or else the one before it, and its
+    // partner. Brackets in comments and strings only pair among themselves.
+    fn matching_bracket(&self) -> Option<(gtk::TextIter, gtk::TextIter)> {
+        let buf = self.text_view().get_buffer().unwrap();
+        let pairs = self.language().map_or_else(brackets::default_brackets, |l| l.brackets.clone());
+
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let mut before = cursor.clone();
+        let candidates = if before.backward_char() { vec![cursor, before] } else { vec![cursor] };
+        let candidates: Vec<_> = candidates.into_iter().filter_map(|bracket| {
+            let found = bracket.get_char().and_then(|c| brackets::partner(c, &pairs))?;
+            Some((bracket, found))
+        }).collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let literals = highlight::literal_tags(&buf);
+        let in_literal = |iter: &gtk::TextIter| literals.iter().any(|tag| iter.has_tag(tag));
+
+        for (bracket, (partner, forward)) in candidates {
+            let literal = in_literal(&bracket);
+            let mut iter = bracket.clone();
+            let chars = iter::from_fn(|| {
+                let moved = if forward { iter.forward_char() } else { iter.backward_char() };
+                if moved { Some(iter.clone()) } else { None }
+            }).take(BRACKET_SCAN_CHARS)
+                .filter(|i| in_literal(i) == literal)
+                .map(|i| (i.get_char().unwrap_or('\0'), i));
+
+            let c = bracket.get_char().unwrap();
+            if let Some(found) = brackets::find_partner(chars, c, partner) {
+                return Some((bracket, found));
+            }
+        }
+        None
+    }
+
+    // Marks the brackets at the cursor and forgets closing characters typed
+    // over from other lines. Marks follow the brackets marked last time so
+    // that only they need unmarking.
+    fn update_brackets(&self) {
+        let buf = self.text_view().get_buffer().unwrap();
+        let matched: Vec<gtk::TextMark> = self.borrow_mut().matched.drain(..).collect();
+        for mark in matched {
+            let start = buf.get_iter_at_mark(&mark);
+            let mut end = start.clone();
+            end.forward_char();
+            buf.remove_tag_by_name("bracket-match", &start, &end);
+            buf.delete_mark(&mark);
+        }
+
+        let line = buf.get_iter_at_mark(&buf.get_insert().unwrap()).get_line();
+        let stale: Vec<gtk::TextMark> = self.borrow().closers.iter()
+            .filter(|m| buf.get_iter_at_mark(m).get_line() != line)
+            .cloned()
+            .collect();
+        if !stale.is_empty() {
+            self.borrow_mut().closers.retain(|m| !stale.contains(m));
+            for mark in stale {
+                buf.delete_mark(&mark);
+            }
+        }
+
+        if let Some((bracket, partner)) = self.matching_bracket() {
+            for iter in &[bracket, partner] {
+                let mut end = iter.clone();
+                end.forward_char();
+                buf.apply_tag_by_name("bracket-match", iter, &end);
+                let mark = buf.create_mark(None, iter, false).unwrap();
+                self.borrow_mut().matched.push(mark);
+            }
+        }
+    }
+
+    // Typing and moving the cursor both call for an update, often several
+    // times per keystroke, so it is done once the main loop is idle.
+    fn queue_update_brackets(&self) {
+        if self.borrow().brackets_pending {
+            return;
+        }
+        self.borrow_mut().brackets_pending = true;
+
+        let p = self.clone();
+        gtk::idle_add(move || {
+            p.borrow_mut().brackets_pending = false;
+            p.update_brackets();
+            Continue(false)
+        });
+    }
+
+    // Moves the cursor to the same side of the partner as it is of the
+    // bracket, so that jumping again comes back.
+    fn jump_to_bracket(&self) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (bracket, mut partner) = match self.matching_bracket() {
+            Some(found) => found,
+            None => return,
+        };
+        if bracket != buf.get_iter_at_mark(&buf.get_insert().unwrap()) {
+            partner.forward_char();
+        }
+        buf.place_cursor(&partner);
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    // Selects both brackets and everything between, with the cursor on the
+    // side of the partner.
+    fn select_to_bracket(&self) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let (bracket, partner) = match self.matching_bracket() {
+            Some(found) => found,
+            None => return,
+        };
+        if partner > bracket {
+            let mut end = partner.clone();
+            end.forward_char();
+            buf.select_range(&end, &bracket);
+        } else {
+            let mut end = bracket.clone();
+            end.forward_char();
+            buf.select_range(&partner, &end);
+        }
+        text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
+    }
+
+    // Closes a pair as its opening character is typed, wraps the selection
+    // in it, or types over a closing character inserted that way.
+    fn type_char(&self, c: char) -> bool {
+        let text_view = self.text_view();
+        if !self.borrow().auto_close || !text_view.get_editable() {
+            return false;
+        }
+        let buf = text_view.get_buffer().unwrap();
+        let pairs = self.language().map_or_else(brackets::default_auto_close, |l| l.auto_close.clone());
+
+        if let Some((mut start, mut end)) = buf.get_selection_bounds() {
+            let close = match brackets::wrapping(c, &pairs) {
+                Some(close) => close,
+                None => return false,
+            };
+            let text = start.get_slice(&end).unwrap_or_default();
+            let offset = start.get_offset() + 1;
+            buf.begin_user_action();
+            buf.delete(&mut start, &mut end);
+            buf.insert(&mut start, &format!("{}{}{}", c, text, close));
+            buf.end_user_action();
+            let start = buf.get_iter_at_offset(offset);
+            let end = buf.get_iter_at_offset(offset + text.chars().count() as i32);
+            buf.select_range(&end, &start);
+            return true;
+        }
+
+        let mut cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let after = if cursor.is_end() { None } else { cursor.get_char() };
+        let closer = self.borrow().closers.iter().position(|m| buf.get_iter_at_mark(m) == cursor);
+        if let (Some(i), true) = (closer, after == Some(c)) {
+            let mark = self.borrow_mut().closers.remove(i);
+            buf.delete_mark(&mark);
+            cursor.forward_char();
+            buf.place_cursor(&cursor);
+            return true;
+        }
+
+        let mut before = cursor.clone();
+        let before = if before.backward_char() { before.get_char() } else { None };
+        let close = match brackets::closing(c, before, after, &pairs) {
+            Some(close) => close,
+            None => return false,
+        };
+        buf.begin_user_action();
+        buf.insert(&mut cursor, &format!("{}{}", c, close));
+        buf.end_user_action();
+        cursor.backward_char();
+        buf.place_cursor(&cursor);
+        let mark = buf.create_mark(None, &cursor, false).unwrap();
+        self.borrow_mut().closers.push(mark);
+        true
+    }
+
+    // Backspace between a pair that was just closed removes both halves.
+    fn delete_pair(&self) -> bool {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        if !self.borrow().auto_close || !text_view.get_editable() || buf.get_has_selection() {
+            return false;
+        }
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        let i = match self.borrow().closers.iter().position(|m| buf.get_iter_at_mark(m) == cursor) {
+            Some(i) => i,
+            None => return false,
+        };
+
+        let pairs = self.language().map_or_else(brackets::default_auto_close, |l| l.auto_close.clone());
+        let mut start = cursor.clone();
+        let mut end = cursor.clone();
+        if !start.backward_char() || !end.forward_char() ||
+            start.get_char().and_then(|c| brackets::wrapping(c, &pairs)) != cursor.get_char() {
+            return false;
+        }
+
+        let mark = self.borrow_mut().closers.remove(i);
+        buf.delete_mark(&mark);
+        buf.begin_user_action();
+        buf.delete(&mut start, &mut end);
+        buf.end_user_action();
+        true
+    }
+
+    // Files without indented lines get the preferred style.
+    fn detect_indent(&self, text: &str) {
+        let detected = indent::detect(text);
+        let prefs = prefs::get();
+        self.borrow_mut().detected_indent = detected;
+        self.set_indent_width(detected.and_then(|d| d.width).unwrap_or(prefs.tab_width));
+        self.set_indent_spaces(detected.map_or(prefs.insert_spaces, |d| d.spaces));
+    }
+
+    fn goto(&self, pos: Position) {
+        let text_view = self.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let line = cmp::min(cmp::max(pos.line as i32, 1), buf.get_line_count()) - 1;
+
+        let start = buf.get_iter_at_line(line);
+        let mut iter = start.clone();
+        if !iter.ends_line() {
+            iter.forward_to_line_end();
+        }
+        let column = cmp::min(pos.column.unwrap_or(1).saturating_sub(1) as i32, iter.get_line_offset());
+        iter.set_line_offset(column);
+
+        buf.place_cursor(&iter);
+        text_view.scroll_to_mark(&buf.get_insert().unwrap(), 0.0, true, 0.0, 0.5);
+
+        let mut end = start.clone();
+        end.forward_line();
+        buf.apply_tag_by_name("goto-flash", &start, &end);
+        let buf = buf.clone();
+        gtk::timeout_add(FLASH_MILLIS, move || {
+            let (start, end) = buf.get_bounds();
+            buf.remove_tag_by_name("goto-flash", &start, &end);
+            Continue(false)
+        });
+    }
+}
+
+
+pub type Pages = Rc<RefCell<Vec<Page>>>;
+
+pub trait PagesExtend {
+    fn create() -> Pages;
+    fn create_new_page(&self, wins: Windows, win: Window) -> Page;
+    fn len(&self) -> usize;
+    fn append(&self, page: Page);
+    fn uris(&self) -> Vec<Option<String>>;
+    fn get_page(&self, file: &gio::File) -> Option<Page>;
+    fn remove(&self, i: usize);
+}
+
+impl PagesExtend for Pages {
+    fn create() -> Pages {
+        Rc::new(RefCell::new(Vec::<Page>::new()))
+    }
+
+    fn create_new_page(&self, wins: Windows, win: Window) -> Page {
+        let page = Page::create(wins.clone(), win);
+        self.append(page.clone());
+
+        page
+    }
+
+    fn len(&self) -> usize {
+        self.borrow().len()
+    }
+
+    fn append(&self, page: Page) {
+        self.borrow_mut().push(page);
+    }
+
+    fn uris(&self) -> Vec<Option<String>> {
+        self.borrow().iter().map(|p| p.borrow().doc.uri().map(|s| s.to_string())).collect()
+    }
+
+    fn get_page(&self, file: &gio::File) -> Option<Page> {
+        let uri = file.get_uri()?;
+        let mut registry = Registry::new();
+        registry.add_window(self.uris());
+        registry.find(&uri).map(|(_, p)| self.borrow()[p].clone())
+    }
+
+    fn remove(&self, i: usize) {
+        self.borrow_mut().remove(i);
+    }
+}
+
+impl Storage for gio::File {
+    fn uri(&self) -> Option<String> {
+        self.get_uri()
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        self.get_path()
+    }
+
+    fn load(&self) -> Result<(Vec<u8>, Option<String>), String> {
+        self.load_contents(None).map(|(bytes, etag)| (bytes, Some(etag))).map_err(|e| e.to_string())
+    }
+
+    fn current_etag(&self) -> Option<String> {
+        self.query_info("etag::value", gio::FILE_QUERY_INFO_NONE, None)
+            .ok()
+            .and_then(|info| info.get_etag())
+    }
+
+    fn store(&self, bytes: &[u8], etag: Option<&str>, keep_old: bool) -> Result<String, String> {
+        self.replace_contents(bytes, etag, keep_old, gio::FILE_CREATE_NONE, None).map_err(|e| e.to_string())
+    }
+}
+
+type EtagCallback = Box<dyn FnOnce(Option<String>)>;
+
+unsafe extern "C" fn etag_ready(source: *mut gobject_sys::GObject, res: *mut gio_sys::GAsyncResult,
+                                data: glib_sys::gpointer) {
+    let done: Box<EtagCallback> = Box::from_raw(data as *mut EtagCallback);
+    let mut error = ptr::null_mut();
+    let info = gio_sys::g_file_query_info_finish(source as *mut gio_sys::GFile, res, &mut error);
+    let etag = if error.is_null() {
+        let info: gio::FileInfo = from_glib_full(info);
+        info.get_etag()
+    } else {
+        glib_sys::g_error_free(error);
+        None
+    };
+    done(etag);
+}
+
+// Like Storage::current_etag, but without blocking the main loop on a slow
+// or remote file. `done` runs on the main loop.
+fn query_etag<F: FnOnce(Option<String>) + 'static>(file: &gio::File, done: F) {
+    let done: Box<EtagCallback> = Box::new(Box::new(done));
+    unsafe {
+        gio_sys::g_file_query_info_async(
+            file.to_glib_none().0, "etag::value".to_glib_none().0, gio_sys::G_FILE_QUERY_INFO_NONE,
+            glib_sys::G_PRIORITY_DEFAULT, ptr::null_mut(), Some(etag_ready), Box::into_raw(done) as glib_sys::gpointer);
+    }
+}
+
+fn line_bounds(buf: &gtk::TextBuffer, line: i32) -> (gtk::TextIter, gtk::TextIter) {
+    let start = buf.get_iter_at_line(line);
+    let mut end = start.clone();
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    (start, end)
+}
+
+// The line and display column under a point of the text window. Past the
+// end of a line the column keeps counting in widths of a space.
+fn block_point(text_view: &gtk::TextView, x: f64, y: f64, tab_width: u32) -> (usize, usize) {
+    let buf = text_view.get_buffer().unwrap();
+    let (x, y) = text_view.window_to_buffer_coords(gtk::TextWindowType::Text, x as i32, y as i32);
+    let line = text_view.get_line_at_y(y).0.get_line();
+    let (start, end) = line_bounds(&buf, line);
+    let text = start.get_slice(&end).unwrap_or_default();
+
+    let end_x = text_view.get_iter_location(&end).x;
+    if x < end_x {
+        if let Some((iter, trailing)) = text_view.get_iter_at_position(x, y) {
+            if iter.get_line() == line {
+                let index = (iter.get_line_offset() + trailing) as usize;
+                return (line as usize, block::column_at(&text, index, tab_width));
+            }
+        }
+    }
+
+    let space = text_view.create_pango_layout(" ").map_or(8, |l| cmp::max(l.get_pixel_size().0, 1));
+    let past = ((x - end_x) as f64 / space as f64).round().max(0.0) as usize;
+    (line as usize, block::column_at(&text, text.chars().count(), tab_width) + past)
+}
+
+// Extra carets are drawn over the text view, which draws only its own.
+fn draw_carets(text_view: &gtk::TextView, area: &gtk::DrawingArea, cr: &::cairo::Context,
+               marks: &[(gtk::TextMark, gtk::TextMark)]) {
+    let buf = text_view.get_buffer().unwrap();
+    let color = text_view.get_style_context().unwrap().get_color(gtk::STATE_FLAG_NORMAL);
+    cr.set_source_rgba(color.red, color.green, color.blue, color.alpha);
+
+    let visible = text_view.get_visible_rect();
+    let (x, y) = text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, visible.x, visible.y);
+    if let Some((x, y)) = text_view.translate_coordinates(area, x, y) {
+        cr.rectangle(x as f64, y as f64, visible.width as f64, visible.height as f64);
+        cr.clip();
+    }
+
+    for &(ref head, _) in marks {
+        let rect = text_view.get_iter_location(&buf.get_iter_at_mark(head));
+        let (x, y) = text_view.buffer_to_window_coords(gtk::TextWindowType::Widget, rect.x, rect.y);
+        if let Some((x, y)) = text_view.translate_coordinates(area, x, y) {
+            cr.rectangle(x as f64, y as f64, 1.0, rect.height as f64);
+        }
+    }
+    cr.fill();
+}
+
+pub fn show_error(win: &Window, msg: &str) {
+    let dialog = gtk::MessageDialog::new(Some(&win.win()),
+                                         gtk::DIALOG_MODAL,
+                                         gtk::MessageType::Error,
+                                         gtk::ButtonsType::Close,
+                                         msg);
+    run_dialog(win, &dialog);
+    dialog.destroy();
+}
+
+fn run_dialog<D: Cast + IsA<gtk::Dialog> + Clone>(win: &Window, dialog: &D) -> i32 {
+    win.dialogs().run(&dialog.clone().upcast())
+}
@@ -0,0 +1,239 @@
+extern crate gtk;
+extern crate gio;
+extern crate gdk;
+
+use gtk::{
+    WidgetExt, WindowExt, EntryExt, SearchEntryExt, PopoverExt,
+    ListStoreExt, ListStoreExtManual, TreeViewExt,
+    TreeModelExt, Inhibit
+};
+use gio::MenuModelExt;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use win::{Window, WindowExtend};
+use keymap;
+use fuzzy;
+
+struct Command {
+    label: String,
+    action: String,
+    accel: String,
+}
+
+thread_local!(static MENUS: RefCell<Vec<gio::MenuModel>> = RefCell::new(Vec::new()));
+
+// The labels of these menus are shown instead of action names, e.g.
+// "Save As" rather than "win.saveas". They are read each time the palette
+// opens since some sections, like the recent files, change at run time.
+pub fn set_menus(menus: &[gio::MenuModel]) {
+    MENUS.with(|m| *m.borrow_mut() = menus.to_vec());
+}
+
+// Items with a target are listed as commands of their own, under the label
+// of the submenu they belong to.
+fn menu_labels() -> HashMap<String, String> {
+    let mut labels = Vec::new();
+    MENUS.with(|m| {
+        for menu in m.borrow().iter() {
+            collect_labels(menu, None, &mut labels);
+        }
+    });
+    labels.into_iter().collect()
+}
+
+fn clean_label(label: &str) -> String {
+    label.trim_end_matches("...").replace("__", "\u{0}").replace('_', "").replace('\u{0}', "_")
+}
+
+fn collect_labels(menu: &gio::MenuModel, parent: Option<&str>, labels: &mut Vec<(String, String)>) {
+    for i in 0..menu.get_n_items() {
+        let label = menu.get_item_attribute_value(i, "label", None)
+            .and_then(|v| v.get_str().map(clean_label));
+        let action = menu.get_item_attribute_value(i, "action", None)
+            .and_then(|v| v.get_str().map(|s| s.to_string()));
+        let target = menu.get_item_attribute_value(i, "target", None)
+            .and_then(|v| v.get_str().map(|s| s.to_string()));
+
+        if let (Some(ref label), Some(ref action)) = (label.as_ref(), action.as_ref()) {
+            match (target, parent) {
+                (Some(target), Some(parent)) => {
+                    labels.push((format!("{}::{}", action, target), format!("{}: {}", parent, label)));
+                },
+                (Some(target), None) => labels.push((format!("{}::{}", action, target), label.to_string())),
+                (None, _) => labels.push((action.to_string(), label.to_string())),
+            }
+        }
+
+        if let Some(section) = menu.get_item_link(i, "section") {
+            collect_labels(&section, parent, labels);
+        }
+        if let Some(submenu) = menu.get_item_link(i, "submenu") {
+            collect_labels(&submenu, label.as_ref().map(|l| l.as_str()), labels);
+        }
+    }
+}
+
+// "win.close_tab" -> "Close Tab"
+fn action_label(action: &str) -> String {
+    let name = action.splitn(2, '.').nth(1).unwrap_or(action);
+    let words: Vec<String> = name.split('_').filter(|w| !w.is_empty()).map(|w| {
+        let mut chars = w.chars();
+        match chars.next() {
+            Some(c) => c.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }).collect();
+    words.join(" ")
+}
+
+fn commands(app: &gtk::Application, win: &Window) -> Vec<Command> {
+    let menu_labels = menu_labels();
+    let bindings = keymap::bindings();
+    let win = win.win();
+
+    let mut actions = keymap::actions(app, Some(&win));
+    for action in menu_labels.keys() {
+        if action.contains("::") && !actions.contains(action) {
+            actions.push(action.clone());
+        }
+    }
+    actions.retain(|a| a != "win.command_palette");
+
+    let mut commands: Vec<Command> = actions.into_iter().map(|action| {
+        let accel = bindings.get(&action)
+            .map_or(Vec::new(), |shortcuts| shortcuts.iter().map(|s| keymap::label(s)).collect::<Vec<String>>())
+            .join(", ");
+        Command {
+            label: menu_labels.get(&action).cloned().unwrap_or_else(|| action_label(&action)),
+            action: action,
+            accel: accel,
+        }
+    }).collect();
+    commands.sort_by(|a, b| a.label.cmp(&b.label));
+    commands
+}
+
+pub struct CommandPalette {
+    popover: gtk::Popover,
+    entry: gtk::SearchEntry,
+    view: gtk::TreeView,
+    store: gtk::ListStore,
+    commands: Rc<RefCell<Vec<Command>>>,
+}
+
+impl CommandPalette {
+    pub fn new(builder: &gtk::Builder) -> CommandPalette {
+        CommandPalette {
+            popover: builder.get_object("palette_popover").unwrap(),
+            entry: builder.get_object("palette_entry").unwrap(),
+            view: builder.get_object("palette_view").unwrap(),
+            store: builder.get_object("palette_store").unwrap(),
+            commands: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn connect(&self, win: &Window) {
+        {
+            let store = self.store.clone();
+            let view = self.view.clone();
+            let commands = self.commands.clone();
+            self.entry.connect_search_changed(move |entry| {
+                filter(&store, &view, &commands.borrow(), &entry.get_text().unwrap_or_default());
+            });
+        }
+
+        {
+            let view = self.view.clone();
+            self.entry.connect_key_press_event(move |_, event| {
+                let step = match event.get_keyval() {
+                    gdk::enums::key::Down => 1,
+                    gdk::enums::key::Up => -1,
+                    _ => return Inhibit(false),
+                };
+                move_cursor(&view, step);
+                Inhibit(true)
+            });
+        }
+
+        {
+            let win = win.clone();
+            let popover = self.popover.clone();
+            let view = self.view.clone();
+            let store = self.store.clone();
+            self.entry.connect_activate(move |_| {
+                if let (Some(path), _) = view.get_cursor() {
+                    run(&win, &popover, &store, &path);
+                }
+            });
+        }
+
+        {
+            let win = win.clone();
+            let popover = self.popover.clone();
+            let store = self.store.clone();
+            self.view.connect_row_activated(move |_, path, _| {
+                run(&win, &popover, &store, path);
+            });
+        }
+    }
+
+    pub fn show(&self, win: &Window) {
+        let app = match win.win().get_application() {
+            Some(app) => app,
+            None => return,
+        };
+        *self.commands.borrow_mut() = commands(&app, win);
+
+        let width = self.popover.get_relative_to().map_or(0, |w| w.get_allocated_width());
+        self.popover.set_pointing_to(&gtk::Rectangle { x: width / 2, y: 0, width: 1, height: 1 });
+        self.entry.set_text("");
+        filter(&self.store, &self.view, &self.commands.borrow(), "");
+        self.popover.show();
+        self.entry.grab_focus();
+    }
+}
+
+fn filter(store: &gtk::ListStore, view: &gtk::TreeView, commands: &[Command], query: &str) {
+    let mut matches: Vec<(i32, &Command)> = commands.iter().filter_map(|c| {
+        let score = match (fuzzy::score(query, &c.label), fuzzy::score(query, &c.action)) {
+            (Some(a), Some(b)) => Some(if a > b { a } else { b }),
+            (a, b) => a.or(b),
+        };
+        score.map(|s| (s, c))
+    }).collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.label.len().cmp(&b.1.label.len())));
+
+    store.clear();
+    for &(_, c) in &matches {
+        store.insert_with_values(None, &[0, 1, 2], &[&c.label, &c.accel, &c.action]);
+    }
+    if !matches.is_empty() {
+        view.set_cursor(&gtk::TreePath::new_first(), None, false);
+    }
+}
+
+fn move_cursor(view: &gtk::TreeView, step: i32) {
+    let rows = view.get_model().map_or(0, |m| m.iter_n_children(None));
+    if rows == 0 {
+        return;
+    }
+    let current = match view.get_cursor() {
+        (Some(path), _) => path.get_indices().first().cloned().unwrap_or(0),
+        _ => 0,
+    };
+    let next = (current + step + rows) % rows;
+    let path = gtk::TreePath::new_from_indicesv(&[next]);
+    view.set_cursor(&path, None, false);
+    view.scroll_to_cell(&path, None, false, 0.0, 0.0);
+}
+
+fn run(win: &Window, popover: &gtk::Popover, store: &gtk::ListStore, path: &gtk::TreePath) {
+    let action = store.get_iter(path).and_then(|iter| store.get_value(&iter, 2).get::<String>());
+    popover.hide();
+    if let (Some(action), Some(app)) = (action, win.win().get_application()) {
+        keymap::activate(&app, &win.win(), &action);
+    }
+}
@@ -0,0 +1,208 @@
+extern crate glib;
+extern crate gio;
+extern crate toml;
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use gio::{ FileExt, FileInfoExt };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupMode {
+    None,
+    Tilde,
+    Timestamped,
+}
+
+impl BackupMode {
+    pub fn id(&self) -> &'static str {
+        match *self {
+            BackupMode::None => "none",
+            BackupMode::Tilde => "tilde",
+            BackupMode::Timestamped => "timestamped",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<BackupMode> {
+        match id {
+            "none" => Some(BackupMode::None),
+            "tilde" => Some(BackupMode::Tilde),
+            "timestamped" => Some(BackupMode::Timestamped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub backup: BackupMode,
+    pub backup_dir: Option<String>,
+    pub autosave: bool,
+    pub line_numbers: bool,
+    pub font: String,
+    pub tab_width: u32,
+    pub insert_spaces: bool,
+    pub wrap_lines: bool,
+    pub highlight_current_line: bool,
+    pub window_width: i32,
+    pub window_height: i32,
+    pub auto_close: bool,
+    // Languages, by id, that differ from `auto_close`. Kept last since TOML
+    // tables have to follow plain values.
+    pub auto_close_languages: BTreeMap<String, bool>,
+}
+
+impl Default for Preferences {
+    fn default() -> Preferences {
+        Preferences {
+            backup: BackupMode::Tilde,
+            backup_dir: None,
+            autosave: false,
+            line_numbers: true,
+            font: "Monospace 11".to_string(),
+            tab_width: 4,
+            insert_spaces: false,
+            wrap_lines: false,
+            highlight_current_line: true,
+            window_width: 800,
+            window_height: 600,
+            auto_close: true,
+            auto_close_languages: BTreeMap::new(),
+        }
+    }
+}
+
+pub const MAX_TAB_WIDTH: u32 = 16;
+
+impl Preferences {
+    // Brings values edited by hand back into the ranges the editor handles.
+    pub fn clamp(&mut self) {
+        self.tab_width = self.tab_width.clamp(1, MAX_TAB_WIDTH);
+        self.window_width = cmp::max(self.window_width, 1);
+        self.window_height = cmp::max(self.window_height, 1);
+    }
+
+    pub fn backup_dir(&self) -> PathBuf {
+        match self.backup_dir {
+            Some(ref dir) => PathBuf::from(dir),
+            None => data_dir().join("backups"),
+        }
+    }
+
+    pub fn auto_close_for(&self, language: Option<&str>) -> bool {
+        language.and_then(|id| self.auto_close_languages.get(id).cloned()).unwrap_or(self.auto_close)
+    }
+
+    pub fn set_auto_close_for(&mut self, language: Option<&str>, on: bool) {
+        match language {
+            None => self.auto_close = on,
+            Some(id) if on == self.auto_close => {
+                self.auto_close_languages.remove(id);
+            },
+            Some(id) => {
+                self.auto_close_languages.insert(id.to_string(), on);
+            },
+        }
+    }
+}
+
+thread_local!(static PREFS: RefCell<Preferences> = RefCell::new(Preferences::default()));
+thread_local!(static CONFIG_ETAG: RefCell<Option<String>> = RefCell::new(None));
+
+pub fn config_dir() -> PathBuf {
+    glib::get_user_config_dir().unwrap_or_default().join("vanilla_text")
+}
+
+pub fn data_dir() -> PathBuf {
+    glib::get_user_data_dir().unwrap_or_default().join("vanilla_text")
+}
+
+pub fn state_dir() -> PathBuf {
+    let state = match env::var_os("XDG_STATE_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => glib::get_home_dir().unwrap_or_default().join(".local").join("state"),
+    };
+
+    state.join("vanilla_text")
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+pub fn get() -> Preferences {
+    PREFS.with(|p| p.borrow().clone())
+}
+
+pub fn set(mut prefs: Preferences) {
+    prefs.clamp();
+    PREFS.with(|p| *p.borrow_mut() = prefs);
+    if let Err(e) = save() {
+        eprintln!("Cannot write {}: {}", config_path().display(), e);
+    }
+}
+
+pub fn load() {
+    CONFIG_ETAG.with(|e| *e.borrow_mut() = config_etag());
+    if let Some(prefs) = read() {
+        PREFS.with(|p| *p.borrow_mut() = prefs);
+    }
+}
+
+// Re-reads the config file when it has been changed on disk since it was
+// last loaded or saved. Returns true if the preferences changed.
+pub fn reload() -> bool {
+    let etag = config_etag();
+    if etag.is_none() || CONFIG_ETAG.with(|e| *e.borrow() == etag) {
+        return false;
+    }
+    CONFIG_ETAG.with(|e| *e.borrow_mut() = etag);
+
+    match read() {
+        Some(ref prefs) if *prefs != get() => {
+            PREFS.with(|p| *p.borrow_mut() = prefs.clone());
+            true
+        },
+        _ => false,
+    }
+}
+
+fn config_etag() -> Option<String> {
+    gio::File::new_for_path(config_path())
+        .query_info("etag::value", gio::FILE_QUERY_INFO_NONE, None)
+        .ok()
+        .and_then(|info| info.get_etag())
+}
+
+fn read() -> Option<Preferences> {
+    let mut text = String::new();
+    if fs::File::open(config_path()).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+        return None;
+    }
+
+    match toml::from_str::<Preferences>(&text) {
+        Ok(mut prefs) => {
+            prefs.clamp();
+            Some(prefs)
+        },
+        Err(e) => {
+            eprintln!("Cannot parse {}: {}", config_path().display(), e);
+            None
+        },
+    }
+}
+
+fn save() -> io::Result<()> {
+    let text = toml::to_string(&get()).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::create_dir_all(config_dir())?;
+    fs::File::create(config_path())?.write_all(text.as_bytes())?;
+    CONFIG_ETAG.with(|e| *e.borrow_mut() = config_etag());
+    Ok(())
+}
@@ -0,0 +1,149 @@
+extern crate gtk;
+
+use gtk::{
+    WidgetExt, WindowExt, DialogExt, ToggleButtonExt, SpinButtonExt,
+    SpinButtonSignals, ComboBoxExt, ComboBoxTextExt, FontButtonExt
+};
+
+use std::rc::Rc;
+
+use prefs::{self, BackupMode, Preferences};
+use resources;
+use syntax;
+
+#[derive(Clone)]
+pub struct PrefsDialog {
+    dialog: gtk::Dialog,
+    font: gtk::FontButton,
+    tab_width: gtk::SpinButton,
+    insert_spaces: gtk::CheckButton,
+    wrap_lines: gtk::CheckButton,
+    line_numbers: gtk::CheckButton,
+    highlight_line: gtk::CheckButton,
+    auto_close_language: gtk::ComboBoxText,
+    auto_close: gtk::CheckButton,
+    backup: gtk::ComboBoxText,
+    autosave: gtk::CheckButton,
+    window_width: gtk::SpinButton,
+    window_height: gtk::SpinButton,
+}
+
+impl PrefsDialog {
+    pub fn new(parent: Option<&gtk::Window>) -> PrefsDialog {
+        let builder = resources::builder("preferences.ui");
+        let dialog: gtk::Dialog = builder.get_object("prefs_dialog").unwrap();
+        dialog.set_transient_for(parent);
+        dialog.add_button("Close", gtk::ResponseType::Close.into());
+
+        let auto_close_language: gtk::ComboBoxText = builder.get_object("prefs_auto_close_language").unwrap();
+        auto_close_language.append("", "All languages");
+        for lang in syntax::languages() {
+            auto_close_language.append(lang.id.as_str(), &lang.name);
+        }
+        auto_close_language.set_active(0);
+
+        PrefsDialog {
+            dialog: dialog,
+            font: builder.get_object("prefs_font").unwrap(),
+            tab_width: builder.get_object("prefs_tab_width").unwrap(),
+            insert_spaces: builder.get_object("prefs_insert_spaces").unwrap(),
+            wrap_lines: builder.get_object("prefs_wrap_lines").unwrap(),
+            line_numbers: builder.get_object("prefs_line_numbers").unwrap(),
+            highlight_line: builder.get_object("prefs_highlight_line").unwrap(),
+            auto_close_language: auto_close_language,
+            auto_close: builder.get_object("prefs_auto_close").unwrap(),
+            backup: builder.get_object("prefs_backup").unwrap(),
+            autosave: builder.get_object("prefs_autosave").unwrap(),
+            window_width: builder.get_object("prefs_window_width").unwrap(),
+            window_height: builder.get_object("prefs_window_height").unwrap(),
+        }
+    }
+
+    // Every change is written to the config file at once and handed to
+    // `on_change` so that it can be applied to the open windows.
+    pub fn connect<F: Fn(&Preferences) + 'static>(&self, on_change: F) {
+        self.load(&prefs::get());
+
+        let on_change = Rc::new(on_change);
+        let commit = {
+            let d = self.clone();
+            Rc::new(move || {
+                let p = d.read(prefs::get());
+                if p != prefs::get() {
+                    prefs::set(p.clone());
+                    on_change(&p);
+                }
+            })
+        };
+
+        {
+            let commit = commit.clone();
+            self.font.connect_font_set(move |_| commit());
+        }
+        for spin in &[&self.tab_width, &self.window_width, &self.window_height] {
+            let commit = commit.clone();
+            spin.connect_value_changed(move |_| commit());
+        }
+        for check in &[&self.insert_spaces, &self.wrap_lines, &self.line_numbers,
+                       &self.highlight_line, &self.auto_close, &self.autosave] {
+            let commit = commit.clone();
+            check.connect_toggled(move |_| commit());
+        }
+        {
+            let commit = commit.clone();
+            self.backup.connect_changed(move |_| commit());
+        }
+        {
+            let d = self.clone();
+            self.auto_close_language.connect_changed(move |_| {
+                d.auto_close.set_active(prefs::get().auto_close_for(d.auto_close_id().as_ref().map(|id| id.as_str())));
+            });
+        }
+
+        self.dialog.connect_response(move |dialog, _| {
+            dialog.destroy();
+        });
+    }
+
+    pub fn show(&self) {
+        self.dialog.present();
+    }
+
+    fn load(&self, p: &Preferences) {
+        self.font.set_font_name(&p.font);
+        self.tab_width.set_value(p.tab_width as f64);
+        self.insert_spaces.set_active(p.insert_spaces);
+        self.wrap_lines.set_active(p.wrap_lines);
+        self.line_numbers.set_active(p.line_numbers);
+        self.highlight_line.set_active(p.highlight_current_line);
+        self.auto_close.set_active(p.auto_close_for(self.auto_close_id().as_ref().map(|id| id.as_str())));
+        self.backup.set_active_id(p.backup.id());
+        self.autosave.set_active(p.autosave);
+        self.window_width.set_value(p.window_width as f64);
+        self.window_height.set_value(p.window_height as f64);
+    }
+
+    fn read(&self, mut p: Preferences) -> Preferences {
+        if let Some(font) = self.font.get_font_name() {
+            p.font = font;
+        }
+        p.tab_width = self.tab_width.get_value_as_int() as u32;
+        p.insert_spaces = self.insert_spaces.get_active();
+        p.wrap_lines = self.wrap_lines.get_active();
+        p.line_numbers = self.line_numbers.get_active();
+        p.highlight_current_line = self.highlight_line.get_active();
+        p.set_auto_close_for(self.auto_close_id().as_ref().map(|id| id.as_str()), self.auto_close.get_active());
+        if let Some(mode) = self.backup.get_active_id().as_ref().and_then(|id| BackupMode::from_id(id)) {
+            p.backup = mode;
+        }
+        p.autosave = self.autosave.get_active();
+        p.window_width = self.window_width.get_value_as_int();
+        p.window_height = self.window_height.get_value_as_int();
+        p
+    }
+
+    // The language whose auto-closing is shown, None for the default.
+    fn auto_close_id(&self) -> Option<String> {
+        self.auto_close_language.get_active_id().and_then(|id| if id.is_empty() { None } else { Some(id) })
+    }
+}
@@ -0,0 +1,127 @@
+extern crate gtk;
+extern crate gio;
+extern crate glib;
+extern crate toml;
+
+use gtk::RecentManagerExt;
+use gio::{ FileExt, MenuExt, MenuItemExt };
+
+use glib::ToVariant;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use prefs;
+
+pub const MAX_RECENT: usize = 10;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct RecentFiles {
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+thread_local!(
+    static RECENT: RefCell<RecentFiles> = RefCell::new(RecentFiles::default());
+    static MENU: RefCell<Option<gio::Menu>> = RefCell::new(None);
+);
+
+pub fn recent_path() -> PathBuf {
+    prefs::state_dir().join("recent.toml")
+}
+
+pub fn load() {
+    let mut text = String::new();
+    if fs::File::open(recent_path()).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+        return;
+    }
+
+    match toml::from_str::<RecentFiles>(&text) {
+        Ok(recent) => RECENT.with(|r| *r.borrow_mut() = recent),
+        Err(e) => eprintln!("Cannot parse {}: {}", recent_path().display(), e),
+    }
+}
+
+fn save() -> io::Result<()> {
+    let text = RECENT.with(|r| toml::to_string(&*r.borrow()))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::create_dir_all(prefs::state_dir())?;
+    fs::File::create(recent_path())?.write_all(text.as_bytes())
+}
+
+pub fn files() -> Vec<String> {
+    RECENT.with(|r| r.borrow().files.clone())
+}
+
+// The section is refilled whenever the list changes.
+pub fn set_menu(menu: gio::Menu) {
+    MENU.with(|m| *m.borrow_mut() = Some(menu));
+    update_menu();
+}
+
+pub fn add(file: &gio::File) {
+    let uri = match file.get_uri() {
+        Some(uri) => uri,
+        None => return,
+    };
+
+    if let Some(manager) = gtk::RecentManager::get_default() {
+        manager.add_item(&uri);
+    }
+
+    RECENT.with(|r| {
+        let files = &mut r.borrow_mut().files;
+        files.retain(|f| *f != uri);
+        files.insert(0, uri);
+        files.truncate(MAX_RECENT);
+    });
+    changed();
+}
+
+pub fn remove(uri: &str) {
+    RECENT.with(|r| r.borrow_mut().files.retain(|f| f != uri));
+    changed();
+}
+
+pub fn clear() {
+    RECENT.with(|r| r.borrow_mut().files.clear());
+    changed();
+}
+
+fn changed() {
+    if let Err(e) = save() {
+        eprintln!("Cannot write {}: {}", recent_path().display(), e);
+    }
+    update_menu();
+}
+
+fn update_menu() {
+    MENU.with(|m| {
+        if let Some(ref menu) = *m.borrow() {
+            menu.remove_all();
+            for uri in files() {
+                let item = gio::MenuItem::new(label(&uri).as_str(), None);
+                item.set_action_and_target_value("win.open_recent", &uri.to_variant());
+                menu.append_item(&item);
+            }
+        }
+    });
+}
+
+// Underscores are doubled so they are not taken as mnemonics.
+fn label(uri: &str) -> String {
+    let file = gio::File::new_for_uri(uri);
+    let name = match file.get_path() {
+        Some(path) => match glib::get_home_dir() {
+            Some(ref home) if path.starts_with(home) => {
+                format!("~/{}", path.strip_prefix(home).unwrap().display())
+            },
+            _ => path.display().to_string(),
+        },
+        None => uri.to_string(),
+    };
+
+    name.replace("_", "__")
+}
@@ -0,0 +1,116 @@
+extern crate toml;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ops::Deref;
+
+use chrono::Local;
+
+use page::PageExtend;
+use prefs;
+use win::{Windows, WindowExtend};
+
+pub const SNAPSHOT_INTERVAL: u32 = 30;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: String,
+    pub uri: Option<String>,
+    pub title: String,
+    pub window: usize,
+    pub cursor: i32,
+    pub encoding: String,
+    pub line_ending: String,
+}
+
+pub fn recovery_dir() -> PathBuf {
+    prefs::state_dir().join("recovery")
+}
+
+pub fn new_id() -> String {
+    format!("{}-{}-{}",
+            Local::now().format("%Y%m%d%H%M%S"),
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+pub fn write(entry: &Entry, text: &str) -> io::Result<()> {
+    let dir = recovery_dir();
+    fs::create_dir_all(&dir)?;
+
+    let meta = toml::to_string(entry).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::File::create(dir.join(format!("{}.txt", entry.id)))?.write_all(text.as_bytes())?;
+    fs::File::create(dir.join(format!("{}.toml", entry.id)))?.write_all(meta.as_bytes())
+}
+
+pub fn remove(id: &str) {
+    let dir = recovery_dir();
+    let _ = fs::remove_file(dir.join(format!("{}.toml", id)));
+    let _ = fs::remove_file(dir.join(format!("{}.txt", id)));
+}
+
+pub fn list() -> Vec<(Entry, String)> {
+    let mut entries = Vec::new();
+    let dir = match fs::read_dir(recovery_dir()) {
+        Ok(dir) => dir,
+        Err(_) => return entries,
+    };
+
+    for e in dir.filter_map(|e| e.ok()) {
+        let path = e.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+
+        let mut meta = String::new();
+        let mut text = String::new();
+        if fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut meta)).is_err() {
+            continue;
+        }
+        if fs::File::open(path.with_extension("txt")).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+            continue;
+        }
+        if let Ok(entry) = toml::from_str::<Entry>(&meta) {
+            entries.push((entry, text));
+        }
+    }
+
+    entries.sort_by(|a, b| (a.0.window, &a.0.id).cmp(&(b.0.window, &b.0.id)));
+    entries
+}
+
+// Writes a snapshot of every modified page that changed since the last
+// snapshot, and drops the snapshots of pages that have since been saved.
+pub fn snapshot_all(wins: &Windows) {
+    for (i, w) in wins.borrow().deref().iter().enumerate() {
+        for p in w.pages().borrow().deref() {
+            if !p.changed() {
+                p.discard_snapshot();
+                continue;
+            }
+            if !p.snapshot_dirty() {
+                continue;
+            }
+
+            let entry = p.recovery_entry(i);
+            if write(&entry, &p.text()).is_ok() {
+                p.set_snapshot_dirty(false);
+            }
+        }
+    }
+}
+
+pub fn autosave_all(wins: &Windows) {
+    for w in wins.borrow().deref() {
+        for p in w.pages().borrow().deref() {
+            if p.changed() && p.file().is_some() {
+                p.autosave();
+            }
+        }
+    }
+}
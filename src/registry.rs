@@ -0,0 +1,120 @@
+// Which files are open where: one list of file URIs per window, one entry
+// per page in the order the window holds its pages. Untitled pages are None.
+pub struct Registry {
+    windows: Vec<Vec<Option<String>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenTarget {
+    // The window already has the file open in this page.
+    Present(usize),
+    // Another window has the file open.
+    Duplicate,
+    New,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { windows: Vec::new() }
+    }
+
+    pub fn add_window(&mut self, uris: Vec<Option<String>>) {
+        self.windows.push(uris);
+    }
+
+    // The first (window, page) that has `uri` open.
+    pub fn find(&self, uri: &str) -> Option<(usize, usize)> {
+        for (w, pages) in self.windows.iter().enumerate() {
+            if let Some(p) = find_in(pages, uri) {
+                return Some((w, p));
+            }
+        }
+
+        None
+    }
+
+    pub fn open_target(&self, window: usize, uri: &str) -> OpenTarget {
+        if let Some(p) = self.windows.get(window).and_then(|pages| find_in(pages, uri)) {
+            OpenTarget::Present(p)
+        } else if self.find(uri).is_some() {
+            OpenTarget::Duplicate
+        } else {
+            OpenTarget::New
+        }
+    }
+
+    // Whether a page other than (window, page) has `uri` open, in which case
+    // saving there would leave two pages editing the same file.
+    pub fn is_open_elsewhere(&self, window: usize, page: usize, uri: &str) -> bool {
+        self.windows.iter().enumerate().any(|(w, pages)| {
+            pages.iter().enumerate().any(|(p, u)| {
+                (w, p) != (window, page) && u.as_ref().is_some_and(|u| u == uri)
+            })
+        })
+    }
+}
+
+fn find_in(pages: &[Option<String>], uri: &str) -> Option<usize> {
+    pages.iter().position(|u| u.as_ref().is_some_and(|u| u == uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Registry {
+        let mut r = Registry::new();
+        r.add_window(vec![Some("file:///a".to_string()), None, Some("file:///b".to_string())]);
+        r.add_window(vec![None, Some("file:///c".to_string())]);
+        r
+    }
+
+    #[test]
+    fn finds_first_page_with_file() {
+        let r = registry();
+        assert_eq!(r.find("file:///b"), Some((0, 2)));
+        assert_eq!(r.find("file:///c"), Some((1, 1)));
+        assert_eq!(r.find("file:///d"), None);
+    }
+
+    #[test]
+    fn untitled_pages_never_match() {
+        let r = registry();
+        assert_eq!(r.find(""), None);
+        assert!(!r.is_open_elsewhere(0, 0, ""));
+    }
+
+    #[test]
+    fn open_in_same_window_presents() {
+        assert_eq!(registry().open_target(1, "file:///c"), OpenTarget::Present(1));
+    }
+
+    #[test]
+    fn open_in_other_window_is_duplicate() {
+        let r = registry();
+        assert_eq!(r.open_target(1, "file:///a"), OpenTarget::Duplicate);
+        assert_eq!(r.open_target(0, "file:///c"), OpenTarget::Duplicate);
+    }
+
+    #[test]
+    fn open_unknown_file_is_new() {
+        let r = registry();
+        assert_eq!(r.open_target(0, "file:///d"), OpenTarget::New);
+        assert_eq!(r.open_target(5, "file:///d"), OpenTarget::New);
+    }
+
+    #[test]
+    fn save_as_conflicts() {
+        let r = registry();
+        assert!(!r.is_open_elsewhere(0, 0, "file:///a"));
+        assert!(r.is_open_elsewhere(0, 1, "file:///a"));
+        assert!(r.is_open_elsewhere(0, 1, "file:///c"));
+        assert!(!r.is_open_elsewhere(0, 1, "file:///d"));
+    }
+}
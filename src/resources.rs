@@ -0,0 +1,26 @@
+extern crate gtk;
+extern crate gio;
+extern crate glib;
+extern crate gdk_pixbuf;
+
+const PREFIX: &str = "/com/github/koji-m/vanilla_text";
+
+static BUNDLE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vanilla_text.gresource"));
+
+pub fn register() {
+    let bytes = glib::Bytes::from_static(BUNDLE);
+    let resource = gio::Resource::new_from_data(&bytes).expect("invalid resource bundle");
+    gio::resources_register(&resource);
+}
+
+pub fn path(name: &str) -> String {
+    format!("{}/{}", PREFIX, name)
+}
+
+pub fn builder(name: &str) -> gtk::Builder {
+    gtk::Builder::new_from_resource(&path(&format!("ui/{}", name)))
+}
+
+pub fn pixbuf(name: &str) -> Option<gdk_pixbuf::Pixbuf> {
+    gdk_pixbuf::Pixbuf::new_from_resource(&path(&format!("assets/{}", name))).ok()
+}
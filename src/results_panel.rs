@@ -0,0 +1,139 @@
+extern crate gtk;
+
+use gtk::{
+    WidgetExt, EntryExt, ButtonExt, ToggleButtonExt,
+    RevealerExt, SearchEntryExt, LabelExt, ListStoreExt,
+    ListStoreExtManual, TreeViewExt, StyleContextExt
+};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use page::Page;
+use win::{Window, Windows, WindowExtend};
+use search::Query;
+
+pub struct SearchResult {
+    pub win: Window,
+    pub page: Page,
+    pub tab: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub preview: String,
+}
+
+pub struct ResultsPanel {
+    revealer: gtk::Revealer,
+    entry: gtk::SearchEntry,
+    case_button: gtk::ToggleButton,
+    word_button: gtk::ToggleButton,
+    regex_button: gtk::ToggleButton,
+    status: gtk::Label,
+    close_button: gtk::Button,
+    view: gtk::TreeView,
+    store: gtk::ListStore,
+    results: Rc<RefCell<Vec<SearchResult>>>,
+}
+
+impl ResultsPanel {
+    pub fn new(builder: &gtk::Builder) -> ResultsPanel {
+        ResultsPanel {
+            revealer: builder.get_object("results_revealer").unwrap(),
+            entry: builder.get_object("results_entry").unwrap(),
+            case_button: builder.get_object("results_case_button").unwrap(),
+            word_button: builder.get_object("results_word_button").unwrap(),
+            regex_button: builder.get_object("results_regex_button").unwrap(),
+            status: builder.get_object("results_status").unwrap(),
+            close_button: builder.get_object("results_close_button").unwrap(),
+            view: builder.get_object("results_view").unwrap(),
+            store: builder.get_object("results_store").unwrap(),
+            results: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn connect(&self, win: &Window, wins: Windows) {
+        {
+            let win = win.clone();
+            let wins = wins.clone();
+            self.entry.connect_search_changed(move |_| {
+                win.update_results(wins.clone());
+            });
+        }
+
+        for button in &[&self.case_button, &self.word_button, &self.regex_button] {
+            let win = win.clone();
+            let wins = wins.clone();
+            button.connect_toggled(move |_| {
+                win.update_results(wins.clone());
+            });
+        }
+
+        {
+            let win = win.clone();
+            self.entry.connect_stop_search(move |_| {
+                win.hide_results_panel();
+            });
+        }
+
+        {
+            let win = win.clone();
+            self.close_button.connect_clicked(move |_| {
+                win.hide_results_panel();
+            });
+        }
+
+        {
+            let win = win.clone();
+            self.view.connect_row_activated(move |_, path, _| {
+                if let Some(&i) = path.get_indices().first() {
+                    win.activate_result(i as usize);
+                }
+            });
+        }
+    }
+
+    pub fn query(&self) -> Query {
+        Query {
+            pattern: self.entry.get_text().unwrap_or_default(),
+            case_sensitive: self.case_button.get_active(),
+            whole_word: self.word_button.get_active(),
+            regex: self.regex_button.get_active(),
+        }
+    }
+
+    pub fn show(&self) {
+        self.revealer.set_reveal_child(true);
+        self.entry.grab_focus();
+    }
+
+    pub fn hide(&self) {
+        self.revealer.set_reveal_child(false);
+        self.set_results(Vec::new());
+    }
+
+    pub fn set_results(&self, results: Vec<SearchResult>) {
+        self.store.clear();
+        for r in &results {
+            self.store.insert_with_values(None, &[0, 1, 2],
+                                          &[&r.tab, &((r.line + 1) as u32), &r.preview]);
+        }
+        *self.results.borrow_mut() = results;
+    }
+
+    pub fn result(&self, i: usize) -> Option<(Window, Page, usize, usize)> {
+        self.results.borrow().get(i).map(|r| {
+            (r.win.clone(), r.page.clone(), r.start, r.end)
+        })
+    }
+
+    pub fn set_status(&self, status: &str, error: bool) {
+        self.status.set_text(status);
+        let style = self.entry.get_style_context().unwrap();
+        if error {
+            style.add_class("error");
+        } else {
+            style.remove_class("error");
+        }
+    }
+}
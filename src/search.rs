@@ -0,0 +1,201 @@
+extern crate regex;
+
+use regex::{Regex, RegexBuilder, Captures};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    pub pattern: String,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct Matcher {
+    regex: Regex,
+    expand: bool,
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        self.pattern.is_empty()
+    }
+
+    pub fn matcher(&self) -> Result<Matcher, String> {
+        let mut pattern = if self.regex {
+            self.pattern.clone()
+        } else {
+            regex::escape(&self.pattern)
+        };
+
+        if self.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Matcher {
+            regex,
+            expand: self.regex,
+        })
+    }
+}
+
+impl Matcher {
+    // Match positions are character offsets, which is what gtk::TextIter
+    // works with. Empty matches are skipped: there is nothing to highlight.
+    pub fn find_all(&self, text: &str) -> Vec<Match> {
+        self.captures_all(text).into_iter().map(|(m, _)| m).collect()
+    }
+
+    pub fn replace_all(&self, text: &str, replacement: &str) -> Vec<(Match, String)> {
+        self.captures_all(text).into_iter().map(|(m, caps)| {
+            let mut dst = String::new();
+            if self.expand {
+                caps.expand(replacement, &mut dst);
+            } else {
+                dst.push_str(replacement);
+            }
+            (m, dst)
+        }).collect()
+    }
+
+    fn captures_all<'t>(&self, text: &'t str) -> Vec<(Match, Captures<'t>)> {
+        let mut result = Vec::new();
+        let mut byte = 0;
+        let mut chars = 0;
+
+        for caps in self.regex.captures_iter(text) {
+            let (start, end) = {
+                let m = caps.get(0).unwrap();
+                (m.start(), m.end())
+            };
+            if start == end {
+                continue;
+            }
+
+            chars += text[byte..start].chars().count();
+            let len = text[start..end].chars().count();
+            result.push((Match { start: chars, end: chars + len }, caps));
+
+            chars += len;
+            byte = end;
+        }
+
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineMatch {
+    pub range: Match,
+    pub line: usize,
+    pub preview: String,
+}
+
+const PREVIEW_CHARS: usize = 120;
+
+// Resolves each match to its zero-based line number and the text of that
+// line, for listing results.
+pub fn line_matches(text: &str, matches: &[Match]) -> Vec<LineMatch> {
+    let mut starts = vec![0];
+    for (i, c) in text.chars().enumerate() {
+        if c == '\n' {
+            starts.push(i + 1);
+        }
+    }
+    let lines: Vec<&str> = text.split('\n').collect();
+
+    matches.iter().map(|m| {
+        let line = match starts.binary_search(&m.start) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let preview = lines[line].trim().chars().take(PREVIEW_CHARS).collect();
+        LineMatch {
+            range: *m,
+            line,
+            preview,
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pattern: &str, regex: bool) -> Query {
+        Query { pattern: pattern.to_string(), case_sensitive: true, whole_word: false, regex }
+    }
+
+    fn m(start: usize, end: usize) -> Match {
+        Match { start, end }
+    }
+
+    #[test]
+    fn offsets_count_characters() {
+        let matcher = query("b", false).matcher().unwrap();
+        assert_eq!(matcher.find_all("äöb日本b"), vec![m(2, 3), m(5, 6)]);
+    }
+
+    #[test]
+    fn literal_search_ignores_case() {
+        let mut q = query("Straße.", false);
+        q.case_sensitive = false;
+        let matcher = q.matcher().unwrap();
+        assert_eq!(matcher.find_all("STRASSE straße. STRAẞE."), vec![m(8, 15), m(16, 23)]);
+        assert_eq!(matcher.find_all("straßex"), vec![]);
+    }
+
+    #[test]
+    fn whole_word_skips_parts_of_words() {
+        let mut q = query("cat", false);
+        q.whole_word = true;
+        assert_eq!(q.matcher().unwrap().find_all("cat catalog cat."), vec![m(0, 3), m(12, 15)]);
+    }
+
+    #[test]
+    fn regex_replacement_expands_captures() {
+        let matcher = query(r"(\w+)=(\d+)", true).matcher().unwrap();
+        let replaced = matcher.replace_all("a=1, é=22", "$2=$1");
+        assert_eq!(replaced, vec![(m(0, 3), "1=a".to_string()), (m(5, 9), "22=é".to_string())]);
+    }
+
+    #[test]
+    fn literal_replacement_keeps_dollar() {
+        let matcher = query("(x)", false).matcher().unwrap();
+        assert_eq!(matcher.replace_all("f(x)", "$1 costs $$5"), vec![(m(1, 4), "$1 costs $$5".to_string())]);
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(query("(", true).matcher().is_err());
+        assert!(query("(", false).matcher().is_ok());
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        let matcher = query("x*", true).matcher().unwrap();
+        assert_eq!(matcher.find_all("axxb"), vec![m(1, 3)]);
+    }
+
+    #[test]
+    fn matches_resolve_to_lines() {
+        let text = "  first 日本\nsecond\n\nthird 日本";
+        let matches = query("日本", false).matcher().unwrap().find_all(text);
+        let lines = line_matches(text, &matches);
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[0].line, lines[0].preview.as_str()), (0, "first 日本"));
+        assert_eq!((lines[1].line, lines[1].preview.as_str()), (3, "third 日本"));
+        assert_eq!(lines[1].range, m(25, 27));
+    }
+}
@@ -0,0 +1,152 @@
+extern crate gtk;
+extern crate gio;
+extern crate toml;
+
+use gtk::{ WindowExt, NotebookExtManual, LabelExt };
+use gio::FileExt;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::ops::Deref;
+
+use page::{PageExtend, PagesExtend};
+use win::{Window, Windows, WindowExtend, WindowsExtend};
+use prefs;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub windows: Vec<WindowState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    pub width: i32,
+    pub height: i32,
+    pub active: u32,
+    #[serde(default)]
+    pub pages: Vec<PageState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PageState {
+    pub uri: String,
+    pub cursor: i32,
+    pub top: i32,
+}
+
+pub fn session_path() -> PathBuf {
+    prefs::state_dir().join("session.toml")
+}
+
+// Pages without a file are left to the recovery journal.
+pub fn capture(wins: &Windows) -> Session {
+    let mut session = Session::default();
+
+    for w in wins.borrow().deref() {
+        let pages = w.pages();
+        if pages.len() == 0 {
+            continue;
+        }
+
+        let (width, height) = w.win().get_size();
+        let mut state = WindowState {
+            width: width,
+            height: height,
+            active: 0,
+            pages: Vec::new(),
+        };
+
+        let current = w.notebook().get_current_page();
+        for (i, p) in pages.borrow().iter().enumerate() {
+            let uri = match p.file().and_then(|f| f.get_uri()) {
+                Some(uri) => uri,
+                None => continue,
+            };
+            if current == Some(i as u32) {
+                state.active = state.pages.len() as u32;
+            }
+            let (cursor, top) = p.view_position();
+            state.pages.push(PageState {
+                uri: uri,
+                cursor: cursor,
+                top: top,
+            });
+        }
+
+        if !state.pages.is_empty() {
+            session.windows.push(state);
+        }
+    }
+
+    session
+}
+
+pub fn save(wins: &Windows) {
+    if let Err(e) = write(&capture(wins)) {
+        eprintln!("Cannot write {}: {}", session_path().display(), e);
+    }
+}
+
+fn write(session: &Session) -> io::Result<()> {
+    let text = toml::to_string(session).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    fs::create_dir_all(prefs::state_dir())?;
+    fs::File::create(session_path())?.write_all(text.as_bytes())
+}
+
+pub fn load() -> Session {
+    let mut text = String::new();
+    if fs::File::open(session_path()).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+        return Session::default();
+    }
+
+    match toml::from_str::<Session>(&text) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Cannot parse {}: {}", session_path().display(), e);
+            Session::default()
+        },
+    }
+}
+
+// Recreates the windows of the last session. Files that no longer exist or
+// that are already open (e.g. restored from the recovery journal) are skipped.
+pub fn restore(app: &gtk::Application, wins: &Windows) -> bool {
+    let mut restored = false;
+
+    for state in load().windows {
+        let files: Vec<(usize, gio::File)> = state.pages.iter()
+            .map(|p| gio::File::new_for_uri(&p.uri))
+            .enumerate()
+            .filter(|&(_, ref f)| f.query_exists(None) && wins.get_page(f).is_none())
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+
+        let w = Window::create(app, wins.clone());
+        if state.width > 0 && state.height > 0 {
+            w.win().set_default_size(state.width, state.height);
+        }
+
+        for &(i, ref file) in &files {
+            w.open(file, wins.clone(), false);
+            if let Some(page) = w.get_page(file) {
+                page.set_view_position(state.pages[i].cursor, state.pages[i].top);
+            }
+        }
+
+        let active = files.iter()
+            .position(|&(i, _)| i == state.active as usize)
+            .unwrap_or(0);
+        if let Some(page) = w.get_page(&files[active].1) {
+            w.present(page.clone());
+            w.win().set_title(&page.tab_label().get_text().unwrap_or_default());
+        }
+        w.init();
+        restored = true;
+    }
+
+    restored
+}
@@ -0,0 +1,131 @@
+extern crate gtk;
+
+use gtk::{
+    WidgetExt, WindowExt, DialogExt, CellRendererTextExt,
+    ListStoreExt, ListStoreExtManual, TreeModelExt
+};
+
+
+use keymap;
+use resources;
+
+#[derive(Clone)]
+pub struct ShortcutsDialog {
+    app: gtk::Application,
+    win: Option<gtk::ApplicationWindow>,
+    dialog: gtk::Dialog,
+    store: gtk::ListStore,
+    keys_cell: gtk::CellRendererText,
+}
+
+impl ShortcutsDialog {
+    pub fn new(app: &gtk::Application, win: Option<gtk::ApplicationWindow>) -> ShortcutsDialog {
+        let builder = resources::builder("shortcuts.ui");
+        let dialog: gtk::Dialog = builder.get_object("shortcuts_dialog").unwrap();
+        dialog.set_transient_for(win.as_ref());
+        dialog.add_button("Reset All", gtk::ResponseType::Reject.into());
+        dialog.add_button("Close", gtk::ResponseType::Close.into());
+
+        ShortcutsDialog {
+            app: app.clone(),
+            win: win,
+            dialog: dialog,
+            store: builder.get_object("shortcuts_store").unwrap(),
+            keys_cell: builder.get_object("shortcuts_keys_cell").unwrap(),
+        }
+    }
+
+    pub fn connect(&self) {
+        self.refresh();
+
+        {
+            let d = self.clone();
+            self.keys_cell.connect_edited(move |_, path, text| {
+                let action = d.store.get_iter(&path)
+                    .and_then(|iter| d.store.get_value(&iter, 0).get::<String>());
+                if let Some(action) = action {
+                    d.rebind(&action, text);
+                }
+            });
+        }
+
+        {
+            let d = self.clone();
+            self.dialog.connect_response(move |dialog, res| {
+                if res == gtk::ResponseType::Reject.into() {
+                    keymap::reset();
+                    keymap::apply(&d.app);
+                    d.refresh();
+                } else {
+                    dialog.destroy();
+                }
+            });
+        }
+    }
+
+    pub fn show(&self) {
+        self.dialog.present();
+    }
+
+    fn refresh(&self) {
+        let bindings = keymap::bindings();
+        let mut actions = keymap::actions(&self.app, self.win.as_ref());
+        actions.extend(bindings.keys().cloned());
+        actions.sort();
+        actions.dedup();
+
+        self.store.clear();
+        for action in &actions {
+            let keys: Vec<String> = bindings.get(action)
+                .map_or(Vec::new(), |shortcuts| shortcuts.iter().map(|s| keymap::label(s)).collect());
+            self.store.insert_with_values(None, &[0, 1], &[action, &keys.join(", ")]);
+        }
+    }
+
+    fn rebind(&self, action: &str, text: &str) {
+        let mut shortcuts = Vec::new();
+        for part in text.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match keymap::parse(part) {
+                Some(s) => shortcuts.push(s),
+                None => {
+                    self.message(gtk::MessageType::Error, gtk::ButtonsType::Ok,
+                                 &format!("\"{}\" is not a valid shortcut.", part));
+                    return;
+                },
+            }
+        }
+
+        for s in &shortcuts {
+            let conflicts = keymap::conflicts(action, s);
+            if conflicts.is_empty() {
+                continue;
+            }
+            let used: Vec<String> = conflicts.iter()
+                .map(|&(ref a, ref c)| format!("{} ({})", a, keymap::label(c)))
+                .collect();
+            let msg = format!("{} conflicts with {}. Remove the other shortcut?", keymap::label(s), used.join(", "));
+            if !self.message(gtk::MessageType::Question, gtk::ButtonsType::YesNo, &msg) {
+                return;
+            }
+            for (other, c) in conflicts {
+                let rest = keymap::shortcuts(&other).into_iter().filter(|x| *x != c).collect();
+                keymap::set(&other, rest);
+            }
+        }
+
+        keymap::set(action, shortcuts);
+        keymap::apply(&self.app);
+        self.refresh();
+    }
+
+    fn message(&self, kind: gtk::MessageType, buttons: gtk::ButtonsType, msg: &str) -> bool {
+        let dialog = gtk::MessageDialog::new(Some(&self.dialog),
+                                             gtk::DIALOG_MODAL,
+                                             kind,
+                                             buttons,
+                                             msg);
+        let res = dialog.run();
+        dialog.destroy();
+        res == gtk::ResponseType::Yes.into()
+    }
+}
@@ -0,0 +1,124 @@
+extern crate gtk;
+extern crate gio;
+
+use gtk::{
+    LabelExt, ButtonExt, MenuButtonExt, TextViewExt, TextBufferExt,
+    EntryExt, EditableSignals, WidgetExt, StyleContextExt
+};
+use gio::{ MenuExt, MenuItemExt };
+
+use glib::ToVariant;
+
+use page::{Page, PageExtend};
+use win::{Window, WindowExtend};
+use location;
+use syntax;
+
+pub struct StatusBar {
+    position: gtk::Button,
+    selection: gtk::Label,
+    encoding_button: gtk::MenuButton,
+    eol_button: gtk::MenuButton,
+    indent_button: gtk::MenuButton,
+    language_button: gtk::MenuButton,
+    overwrite_button: gtk::ToggleButton,
+    goto_popover: gtk::Popover,
+    goto_entry: gtk::Entry,
+}
+
+impl StatusBar {
+    pub fn new(builder: &gtk::Builder) -> StatusBar {
+        let language_button: gtk::MenuButton = builder.get_object("status_language").unwrap();
+        language_button.set_menu_model(Some(&language_menu()));
+
+        StatusBar {
+            position: builder.get_object("status_position").unwrap(),
+            selection: builder.get_object("status_selection").unwrap(),
+            encoding_button: builder.get_object("status_encoding").unwrap(),
+            eol_button: builder.get_object("status_eol").unwrap(),
+            indent_button: builder.get_object("status_indent").unwrap(),
+            language_button: language_button,
+            overwrite_button: builder.get_object("status_overwrite").unwrap(),
+            goto_popover: builder.get_object("goto_popover").unwrap(),
+            goto_entry: builder.get_object("goto_entry").unwrap(),
+        }
+    }
+
+    pub fn connect(&self, win: &Window) {
+        {
+            let win = win.clone();
+            let popover = self.goto_popover.clone();
+            self.goto_entry.connect_activate(move |entry| {
+                let text = entry.get_text().unwrap_or_default();
+                match location::parse_position(&text) {
+                    Some(pos) => {
+                        popover.hide();
+                        win.goto(pos);
+                    },
+                    None => entry.get_style_context().unwrap().add_class("error"),
+                }
+            });
+        }
+
+        self.goto_entry.connect_changed(move |entry| {
+            entry.get_style_context().unwrap().remove_class("error");
+        });
+    }
+
+    pub fn show_goto(&self, page: &Page) {
+        let buf = page.text_view().get_buffer().unwrap();
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        self.goto_entry.set_text(&format!("{}:{}", cursor.get_line() + 1, cursor.get_line_offset() + 1));
+        self.goto_popover.show();
+        self.goto_entry.grab_focus();
+    }
+
+    pub fn update(&self, page: &Page) {
+        let text_view = page.text_view();
+        let buf = text_view.get_buffer().unwrap();
+        let cursor = buf.get_iter_at_mark(&buf.get_insert().unwrap());
+        self.position.set_label(&format!("Ln {}, Col {}", cursor.get_line() + 1, cursor.get_line_offset() + 1));
+
+        match buf.get_selection_bounds() {
+            Some((start, end)) => {
+                self.selection.set_text(&format!("{} selected", end.get_offset() - start.get_offset()));
+            },
+            None => self.selection.set_text(""),
+        }
+
+        self.encoding_button.set_label(page.encoding().name());
+        self.eol_button.set_label(page.line_ending().label());
+        if page.indent_spaces() {
+            self.indent_button.set_label(&format!("Spaces: {}", page.indent_width()));
+        } else {
+            self.indent_button.set_label(&format!("Tab Width: {}", page.indent_width()));
+        }
+        match page.language() {
+            Some(lang) => self.language_button.set_label(&lang.name),
+            None => self.language_button.set_label("Plain Text"),
+        }
+        self.overwrite_button.set_label(if text_view.get_overwrite() { "OVR" } else { "INS" });
+    }
+}
+
+fn language_menu() -> gio::Menu {
+    let menu = gio::Menu::new();
+
+    let plain = gio::Menu::new();
+    let item = gio::MenuItem::new("Plain Text", None);
+    item.set_action_and_target_value("win.language", &"".to_variant());
+    plain.append_item(&item);
+    menu.append_section(None, &plain);
+
+    let languages = gio::Menu::new();
+    let mut list = syntax::languages();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    for lang in list {
+        let item = gio::MenuItem::new(lang.name.as_str(), None);
+        item.set_action_and_target_value("win.language", &lang.id.to_variant());
+        languages.append_item(&item);
+    }
+    menu.append_section(None, &languages);
+
+    menu
+}
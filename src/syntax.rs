@@ -0,0 +1,135 @@
+extern crate toml;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use prefs;
+use brackets;
+use tokenizer::Language;
+
+#[derive(Deserialize)]
+struct LanguageDef {
+    id: String,
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default)]
+    filenames: Vec<String>,
+    first_line: Option<String>,
+    #[serde(default)]
+    indent_after: Vec<String>,
+    #[serde(default = "brackets::default_brackets")]
+    brackets: Vec<String>,
+    #[serde(default = "brackets::default_auto_close")]
+    auto_close: Vec<String>,
+    #[serde(default)]
+    rules: Vec<RuleDef>,
+}
+
+#[derive(Deserialize)]
+struct RuleDef {
+    style: String,
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    begin: Option<String>,
+    end: Option<String>,
+    escape: Option<String>,
+}
+
+// Reads a language definition, whose rules are tried in order.
+pub fn parse(text: &str) -> Result<Language, String> {
+    let def: LanguageDef = toml::from_str(text).map_err(|e| e.to_string())?;
+
+    let mut lang = Language::new(&def.id, &def.name);
+    lang.extensions = def.extensions;
+    lang.filenames = def.filenames;
+    if let Some(ref p) = def.first_line {
+        lang.set_first_line(p)?;
+    }
+    lang.indent_after = def.indent_after;
+    lang.brackets = def.brackets;
+    lang.auto_close = def.auto_close;
+
+    for r in def.rules {
+        match (r.pattern, r.begin, r.end) {
+            (Some(p), None, None) => lang.add_match(&r.style, &p)?,
+            (None, Some(b), Some(e)) => lang.add_region(&r.style, &b, &e, r.escape.as_deref())?,
+            _ => return Err("a rule needs either match or begin and end".to_string()),
+        }
+    }
+
+    Ok(lang)
+}
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("c.toml", include_str!("../languages/c.toml")),
+    ("json.toml", include_str!("../languages/json.toml")),
+    ("markdown.toml", include_str!("../languages/markdown.toml")),
+    ("python.toml", include_str!("../languages/python.toml")),
+    ("rust.toml", include_str!("../languages/rust.toml")),
+    ("shell.toml", include_str!("../languages/shell.toml")),
+    ("toml.toml", include_str!("../languages/toml.toml")),
+    ("yaml.toml", include_str!("../languages/yaml.toml")),
+];
+
+thread_local!(static LANGUAGES: RefCell<Option<Vec<Rc<Language>>>> = RefCell::new(None));
+
+fn add_language(languages: &mut Vec<Rc<Language>>, name: &str, text: &str) {
+    match parse(text) {
+        Ok(lang) => {
+            languages.retain(|l| l.id != lang.id);
+            languages.push(Rc::new(lang));
+        },
+        Err(e) => eprintln!("Cannot parse {}: {}", name, e),
+    }
+}
+
+// Definitions in the user's data directory override the built-in ones.
+fn load_languages() -> Vec<Rc<Language>> {
+    let mut languages: Vec<Rc<Language>> = Vec::new();
+    for &(name, text) in BUILTIN {
+        add_language(&mut languages, name, text);
+    }
+
+    let mut paths: Vec<PathBuf> = match fs::read_dir(prefs::data_dir().join("languages")) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return languages,
+    };
+    paths.sort();
+
+    for path in paths {
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let mut text = String::new();
+        if fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut text)).is_err() {
+            continue;
+        }
+        add_language(&mut languages, &path.display().to_string(), &text);
+    }
+
+    languages
+}
+
+pub fn languages() -> Vec<Rc<Language>> {
+    LANGUAGES.with(|l| {
+        l.borrow_mut().get_or_insert_with(load_languages).clone()
+    })
+}
+
+pub fn for_id(id: &str) -> Option<Rc<Language>> {
+    languages().into_iter().find(|l| l.id == id)
+}
+
+pub fn detect(name: Option<&str>, first_line: &str) -> Option<Rc<Language>> {
+    let languages = languages();
+    if let Some(name) = name {
+        if let Some(lang) = languages.iter().find(|l| l.matches_name(name)) {
+            return Some(lang.clone());
+        }
+    }
+    languages.iter().find(|l| l.matches_first_line(first_line)).cloned()
+}
@@ -0,0 +1,459 @@
+extern crate regex;
+
+use regex::Regex;
+
+use std::cmp;
+use std::iter;
+use std::rc::Rc;
+
+use brackets;
+
+pub const STYLES: &[&str] = &[
+    "comment", "string", "keyword", "type", "constant", "number", "function",
+    "preprocessor", "key", "heading", "emphasis", "strong", "code", "link",
+];
+
+enum Pattern {
+    Match(Regex),
+    Region { begin: Regex, end: Regex, escape: Option<Regex> },
+}
+
+struct Rule {
+    style: &'static str,
+    pattern: Pattern,
+}
+
+pub struct Language {
+    pub id: String,
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    first_line: Option<Regex>,
+    // Line endings after which a new line is indented one level deeper.
+    pub indent_after: Vec<String>,
+    // Pairs matched by the bracket highlight.
+    pub brackets: Vec<String>,
+    // Pairs, quotes included, closed as they are typed.
+    pub auto_close: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Normal,
+    Region(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    pub style: &'static str,
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+fn style(name: &str) -> Result<&'static str, String> {
+    STYLES.iter().find(|s| **s == name).cloned().ok_or_else(|| format!("unknown style \"{}\"", name))
+}
+
+impl Language {
+    pub fn new(id: &str, name: &str) -> Language {
+        Language {
+            id: id.to_string(),
+            name: name.to_string(),
+            extensions: Vec::new(),
+            filenames: Vec::new(),
+            first_line: None,
+            indent_after: Vec::new(),
+            brackets: brackets::default_brackets(),
+            auto_close: brackets::default_auto_close(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn set_first_line(&mut self, pattern: &str) -> Result<(), String> {
+        self.first_line = Some(compile(pattern)?);
+        Ok(())
+    }
+
+    // Rules are tried in the order they are added; the earliest match wins.
+    pub fn add_match(&mut self, style_name: &str, pattern: &str) -> Result<(), String> {
+        let style = style(style_name)?;
+        self.rules.push(Rule { style, pattern: Pattern::Match(compile(pattern)?) });
+        Ok(())
+    }
+
+    pub fn add_region(&mut self, style_name: &str, begin: &str, end: &str, escape: Option<&str>) -> Result<(), String> {
+        let style = style(style_name)?;
+        let pattern = Pattern::Region {
+            begin: compile(begin)?,
+            end: compile(end)?,
+            escape: match escape {
+                Some(esc) => Some(compile(esc)?),
+                None => None,
+            },
+        };
+        self.rules.push(Rule { style, pattern });
+        Ok(())
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        if self.filenames.iter().any(|f| f == name) {
+            return true;
+        }
+        match name.rfind('.') {
+            Some(i) if i > 0 => {
+                let ext = &name[i + 1..];
+                self.extensions.iter().any(|e| e == ext)
+            },
+            _ => false,
+        }
+    }
+
+    pub fn matches_first_line(&self, line: &str) -> bool {
+        self.first_line.as_ref().is_some_and(|re| re.is_match(line))
+    }
+}
+
+fn next_char(line: &str, i: usize) -> usize {
+    i + line[i..].chars().next().map_or(1, |c| c.len_utf8())
+}
+
+// Finds the first non-empty match at or after `pos`. When the pattern has a
+// capture group only the first group is taken.
+fn find(re: &Regex, line: &str, pos: usize) -> Option<(usize, usize)> {
+    let mut from = pos;
+    while from <= line.len() {
+        let range = if re.captures_len() > 1 {
+            let caps = re.captures_at(line, from)?;
+            let m = caps.get(1).unwrap_or_else(|| caps.get(0).unwrap());
+            (m.start(), m.end(), caps.get(0).unwrap().start())
+        } else {
+            let m = re.find_at(line, from)?;
+            (m.start(), m.end(), m.start())
+        };
+
+        if range.0 < range.1 {
+            return Some((range.0, range.1));
+        }
+        if range.2 >= line.len() {
+            return None;
+        }
+        from = next_char(line, range.2);
+    }
+    None
+}
+
+fn region_end(end: &Regex, escape: &Option<Regex>, line: &str, pos: usize) -> Option<usize> {
+    let mut pos = pos;
+    loop {
+        let e = end.find_at(line, pos)?;
+        if let Some(ref escape) = *escape {
+            if let Some(x) = escape.find_at(line, pos) {
+                if x.start() <= e.start() && x.end() > x.start() {
+                    pos = x.end();
+                    continue;
+                }
+            }
+        }
+        return Some(e.end());
+    }
+}
+
+pub fn tokenize_line(lang: &Language, state: State, line: &str) -> (Vec<Token>, State) {
+    let mut tokens = Vec::new();
+    let mut state = state;
+    let mut pos = 0;
+    let mut cache: Vec<Option<Option<(usize, usize)>>> = vec![None; lang.rules.len()];
+
+    loop {
+        match state {
+            State::Region(r) => {
+                let rule = match lang.rules.get(r) {
+                    Some(rule) => rule,
+                    None => return (tokens, State::Normal),
+                };
+                let end = match rule.pattern {
+                    Pattern::Region { ref end, ref escape, .. } => region_end(end, escape, line, pos),
+                    Pattern::Match(_) => Some(pos),
+                };
+                match end {
+                    Some(end) => {
+                        if end > pos {
+                            tokens.push(Token { start: pos, end, style: rule.style });
+                        }
+                        pos = end;
+                        state = State::Normal;
+                    },
+                    None => {
+                        if line.len() > pos {
+                            tokens.push(Token { start: pos, end: line.len(), style: rule.style });
+                        }
+                        return (tokens, state);
+                    },
+                }
+            },
+            State::Normal => {
+                let mut best: Option<(usize, usize, usize)> = None;
+                for (i, rule) in lang.rules.iter().enumerate() {
+                    let stale = match cache[i] {
+                        Some(Some((start, _))) => start < pos,
+                        Some(None) => false,
+                        None => true,
+                    };
+                    if stale {
+                        let re = match rule.pattern {
+                            Pattern::Match(ref re) => re,
+                            Pattern::Region { ref begin, .. } => begin,
+                        };
+                        cache[i] = Some(find(re, line, pos));
+                    }
+                    if let Some(Some((start, end))) = cache[i] {
+                        if best.is_none_or(|b| start < b.1) {
+                            best = Some((i, start, end));
+                        }
+                    }
+                }
+
+                let (i, start, end) = match best {
+                    Some(b) => b,
+                    None => return (tokens, state),
+                };
+                let rule = &lang.rules[i];
+                tokens.push(Token { start, end, style: rule.style });
+                pos = end;
+                if let Pattern::Region { .. } = rule.pattern {
+                    state = State::Region(i);
+                }
+            },
+        }
+    }
+}
+
+// Keeps the tokenizer state at the end of every line so that an edit only
+// re-tokenizes from the damaged line until the states converge again.
+pub struct Highlighter {
+    language: Rc<Language>,
+    states: Vec<Option<State>>,
+    dirty: Option<(usize, usize)>,
+}
+
+impl Highlighter {
+    pub fn new(language: Rc<Language>, lines: usize) -> Highlighter {
+        let lines = cmp::max(lines, 1);
+        Highlighter {
+            language,
+            states: vec![None; lines],
+            dirty: Some((0, lines - 1)),
+        }
+    }
+
+    pub fn language(&self) -> &Rc<Language> {
+        &self.language
+    }
+
+    // `removed` lines after `line` were joined into it and `added` new lines
+    // were inserted after it. A range still waiting to be re-tokenized moves
+    // with the lines below the edit, and is reached even if the states
+    // converge again before it.
+    pub fn edit(&mut self, line: usize, removed: usize, added: usize) {
+        let at = cmp::min(line + 1, self.states.len());
+        let end = cmp::min(at + removed, self.states.len());
+        self.states.splice(at..end, iter::repeat_n(None, added));
+        if self.states.is_empty() {
+            self.states.push(None);
+        }
+
+        let shift = |l: usize| {
+            if l <= line {
+                l
+            } else if l <= line + removed {
+                line + added
+            } else {
+                l - removed + added
+            }
+        };
+        let (first, last) = (line, line + added);
+        self.dirty = Some(match self.dirty {
+            Some((f, l)) => (cmp::min(shift(f), first), cmp::max(cmp::max(shift(f), shift(l)), last)),
+            None => (first, last),
+        });
+    }
+
+    pub fn next_line(&self) -> Option<usize> {
+        self.dirty.map(|(first, _)| first)
+    }
+
+    pub fn start_state(&self, line: usize) -> State {
+        if line == 0 {
+            return State::Normal;
+        }
+        self.states.get(line - 1).and_then(|s| *s).unwrap_or(State::Normal)
+    }
+
+    pub fn update(&mut self, line: usize, state: State) {
+        let last = match self.dirty {
+            Some((_, last)) => last,
+            None => return,
+        };
+        if line >= self.states.len() {
+            self.dirty = None;
+            return;
+        }
+
+        let unchanged = self.states[line] == Some(state);
+        self.states[line] = Some(state);
+        if (unchanged && line >= last) || line + 1 >= self.states.len() {
+            self.dirty = None;
+        } else {
+            self.dirty = Some((line + 1, last));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c_like() -> Rc<Language> {
+        let mut lang = Language::new("c", "C");
+        lang.add_region("comment", r"/\*", r"\*/", None).unwrap();
+        lang.add_match("comment", "//.*").unwrap();
+        lang.add_region("string", "\"", "\"", Some(r"\\.")).unwrap();
+        lang.add_match("keyword", r"\b(?:if|else)\b").unwrap();
+        Rc::new(lang)
+    }
+
+    fn token(start: usize, end: usize, style: &'static str) -> Token {
+        Token { start, end, style }
+    }
+
+    // Re-tokenizes at most `max` damaged lines, as the view does per idle.
+    fn run(h: &mut Highlighter, lines: &[String], max: usize) {
+        for _ in 0..max {
+            let line = match h.next_line() {
+                Some(line) => line,
+                None => return,
+            };
+            let (_, state) = tokenize_line(h.language(), h.start_state(line), &lines[line]);
+            h.update(line, state);
+        }
+    }
+
+    // The state at the end of every line, as a fresh pass would leave it.
+    fn states(lang: &Rc<Language>, lines: &[String]) -> Vec<State> {
+        let mut state = State::Normal;
+        lines.iter().map(|l| {
+            state = tokenize_line(lang, state, l).1;
+            state
+        }).collect()
+    }
+
+    fn highlighted(h: &Highlighter, lines: &[String]) -> Vec<State> {
+        (1..lines.len() + 1).map(|i| h.start_state(i)).collect()
+    }
+
+    fn lines(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("if x{} else", i)).collect()
+    }
+
+    #[test]
+    fn tokenizes_matches_and_regions() {
+        let lang = c_like();
+        let (tokens, state) = tokenize_line(&lang, State::Normal, r#"if "a\"b" /* c"#);
+        assert_eq!(tokens, vec![
+            token(0, 2, "keyword"), token(3, 4, "string"), token(4, 9, "string"),
+            token(10, 12, "comment"), token(12, 14, "comment"),
+        ]);
+        assert_eq!(state, State::Region(0));
+
+        let (tokens, state) = tokenize_line(&lang, state, "c */ else // d");
+        assert_eq!(tokens, vec![token(0, 4, "comment"), token(5, 9, "keyword"), token(10, 14, "comment")]);
+        assert_eq!(state, State::Normal);
+    }
+
+    #[test]
+    fn rejects_unknown_styles() {
+        let mut lang = Language::new("x", "X");
+        assert!(lang.add_match("nonsense", "a").is_err());
+        assert!(lang.add_region("comment", "(", "b", None).is_err());
+    }
+
+    #[test]
+    fn comment_opened_then_closed() {
+        let lang = c_like();
+        let mut text = lines(6);
+        let mut h = Highlighter::new(lang.clone(), text.len());
+        run(&mut h, &text, 100);
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+
+        text[1] = "/* open".to_string();
+        h.edit(1, 0, 0);
+        run(&mut h, &text, 100);
+        assert_eq!(h.start_state(6), State::Region(0));
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+
+        text[3] = "close */".to_string();
+        h.edit(3, 0, 0);
+        run(&mut h, &text, 100);
+        assert_eq!(h.start_state(6), State::Normal);
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+    }
+
+    #[test]
+    fn lines_inserted_while_batch_is_pending() {
+        let lang = c_like();
+        let mut text = lines(200);
+        let mut h = Highlighter::new(lang.clone(), text.len());
+        run(&mut h, &text, 1000);
+
+        text[150] = "/* open".to_string();
+        h.edit(150, 0, 0);
+        run(&mut h, &text, 10);
+
+        for i in 0..5 {
+            text.insert(21 + i, "if".to_string());
+        }
+        h.edit(20, 0, 5);
+        run(&mut h, &text, 1000);
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+    }
+
+    #[test]
+    fn lines_deleted_while_batch_is_pending() {
+        let lang = c_like();
+        let mut text = lines(200);
+        let mut h = Highlighter::new(lang.clone(), text.len());
+        run(&mut h, &text, 1000);
+
+        text[100] = "/* open".to_string();
+        text[150] = "close */".to_string();
+        h.edit(100, 0, 0);
+        h.edit(150, 0, 0);
+        run(&mut h, &text, 10);
+
+        text.drain(21..31);
+        h.edit(20, 10, 0);
+        run(&mut h, &text, 1000);
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+    }
+
+    #[test]
+    fn initial_batch_survives_edits() {
+        let lang = c_like();
+        let mut text = lines(100);
+        text[0] = "/* open".to_string();
+        let mut h = Highlighter::new(lang.clone(), text.len());
+        run(&mut h, &text, 10);
+
+        text.drain(5..15);
+        h.edit(4, 10, 0);
+        text.insert(60, "close */".to_string());
+        h.edit(59, 0, 1);
+        run(&mut h, &text, 1000);
+        assert_eq!(highlighted(&h, &text), states(&lang, &text));
+    }
+}
@@ -0,0 +1,352 @@
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Insert { offset: usize, text: String },
+    Delete { offset: usize, text: String },
+}
+
+impl Edit {
+    fn invert(&self) -> Edit {
+        match *self {
+            Edit::Insert { offset, ref text } => Edit::Delete { offset, text: text.clone() },
+            Edit::Delete { offset, ref text } => Edit::Insert { offset, text: text.clone() },
+        }
+    }
+
+    fn single_char(&self) -> Option<char> {
+        let text = match *self {
+            Edit::Insert { ref text, .. } | Edit::Delete { ref text, .. } => text,
+        };
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c != '\n' => Some(c),
+            _ => None,
+        }
+    }
+
+    // Extends a typing run (or a run of Backspace / Delete presses) with the
+    // next single-character edit. A run breaks where a word starts after
+    // whitespace so that undo removes one word at a time.
+    fn merge(&mut self, next: &Edit) -> bool {
+        let c = match next.single_char() {
+            Some(c) => c,
+            None => return false,
+        };
+
+        match (self, next) {
+            (&mut Edit::Insert { offset, ref mut text },
+             &Edit::Insert { offset: o, text: ref t }) => {
+                let last = text.chars().last();
+                if offset + text.chars().count() != o || breaks_run(last, c) {
+                    return false;
+                }
+                text.push_str(t);
+                true
+            },
+            (&mut Edit::Delete { ref mut offset, ref mut text },
+             &Edit::Delete { offset: o, text: ref t }) => {
+                if o + 1 == *offset {
+                    if breaks_run(Some(c), text.chars().next().unwrap_or(c)) {
+                        return false;
+                    }
+                    text.insert_str(0, t);
+                    *offset = o;
+                    true
+                } else if o == *offset {
+                    if breaks_run(text.chars().last(), c) {
+                        return false;
+                    }
+                    text.push_str(t);
+                    true
+                } else {
+                    false
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+fn breaks_run(prev: Option<char>, next: char) -> bool {
+    match prev {
+        Some(p) => p.is_whitespace() && !next.is_whitespace(),
+        None => false,
+    }
+}
+
+pub struct UndoStack {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    group: Vec<Edit>,
+    depth: usize,
+    mergeable: bool,
+    suspended: bool,
+    // How many groups were on the undo stack when the text was last saved,
+    // None once that state can no longer be reached.
+    saved: Option<usize>,
+}
+
+impl Default for UndoStack {
+    fn default() -> UndoStack {
+        UndoStack::new()
+    }
+}
+
+impl UndoStack {
+    pub fn new() -> UndoStack {
+        UndoStack {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            group: Vec::new(),
+            depth: 0,
+            mergeable: false,
+            suspended: false,
+            saved: Some(0),
+        }
+    }
+
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.commit();
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        if self.suspended {
+            return;
+        }
+        self.group.push(edit);
+        if self.depth == 0 {
+            self.commit();
+        }
+    }
+
+    fn commit(&mut self) {
+        if self.group.is_empty() {
+            return;
+        }
+
+        let group: Vec<Edit> = self.group.drain(..).collect();
+        self.redo.clear();
+        if self.saved.is_some_and(|s| s > self.undo.len()) {
+            self.saved = None;
+        }
+
+        if group.len() == 1 && group[0].single_char().is_some() {
+            if self.mergeable {
+                if let Some(last) = self.undo.last_mut().and_then(|g| g.last_mut()) {
+                    if last.merge(&group[0]) {
+                        return;
+                    }
+                }
+            }
+            self.mergeable = true;
+        } else {
+            self.mergeable = false;
+        }
+
+        self.undo.push(group);
+    }
+
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        self.mergeable = false;
+        let group = self.undo.pop()?;
+        let edits = group.iter().rev().map(|e| e.invert()).collect();
+        self.redo.push(group);
+
+        Some(edits)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.mergeable = false;
+        let group = self.redo.pop()?;
+        let edits = group.clone();
+        self.undo.push(group);
+
+        Some(edits)
+    }
+
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group.clear();
+        self.depth = 0;
+        self.mergeable = false;
+        self.saved = None;
+    }
+
+    // Typing after a save starts a new step, so that undoing it returns to
+    // the saved text.
+    pub fn mark_saved(&mut self) {
+        self.commit();
+        self.mergeable = false;
+        self.saved = Some(self.undo.len());
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.group.is_empty() && self.saved == Some(self.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(offset: usize, text: &str) -> Edit {
+        Edit::Insert { offset, text: text.to_string() }
+    }
+
+    fn delete(offset: usize, text: &str) -> Edit {
+        Edit::Delete { offset, text: text.to_string() }
+    }
+
+    fn type_text(stack: &mut UndoStack, offset: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            stack.record(insert(offset + i, &c.to_string()));
+        }
+    }
+
+    #[test]
+    fn typing_merges_into_one_step() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "abc");
+        assert_eq!(stack.undo(), Some(vec![delete(0, "abc")]));
+        assert_eq!(stack.undo(), None);
+        assert_eq!(stack.redo(), Some(vec![insert(0, "abc")]));
+    }
+
+    #[test]
+    fn typing_breaks_at_word_start() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "ab cd");
+        assert_eq!(stack.undo(), Some(vec![delete(3, "cd")]));
+        assert_eq!(stack.undo(), Some(vec![delete(0, "ab ")]));
+    }
+
+    #[test]
+    fn typing_breaks_elsewhere_and_at_newlines() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "a");
+        type_text(&mut stack, 5, "b");
+        stack.record(insert(6, "\n"));
+        type_text(&mut stack, 7, "c");
+        assert_eq!(stack.undo(), Some(vec![delete(7, "c")]));
+        assert_eq!(stack.undo(), Some(vec![delete(6, "\n")]));
+        assert_eq!(stack.undo(), Some(vec![delete(5, "b")]));
+        assert_eq!(stack.undo(), Some(vec![delete(0, "a")]));
+    }
+
+    #[test]
+    fn backspace_and_delete_runs_merge() {
+        let mut stack = UndoStack::new();
+        stack.record(delete(2, "c"));
+        stack.record(delete(1, "b"));
+        stack.record(delete(0, "a"));
+        assert_eq!(stack.undo(), Some(vec![insert(0, "abc")]));
+
+        let mut stack = UndoStack::new();
+        stack.record(delete(0, "a"));
+        stack.record(delete(0, "b"));
+        assert_eq!(stack.undo(), Some(vec![insert(0, "ab")]));
+    }
+
+    #[test]
+    fn insert_and_delete_do_not_merge() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "ab");
+        stack.record(delete(1, "b"));
+        assert_eq!(stack.undo(), Some(vec![insert(1, "b")]));
+        assert_eq!(stack.undo(), Some(vec![delete(0, "ab")]));
+    }
+
+    #[test]
+    fn group_undoes_in_reverse() {
+        let mut stack = UndoStack::new();
+        stack.begin_group();
+        stack.record(delete(0, "old"));
+        stack.begin_group();
+        stack.record(insert(0, "new"));
+        stack.end_group();
+        stack.end_group();
+        assert_eq!(stack.undo(), Some(vec![delete(0, "new"), insert(0, "old")]));
+        assert_eq!(stack.redo(), Some(vec![delete(0, "old"), insert(0, "new")]));
+    }
+
+    #[test]
+    fn undo_ends_typing_run() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "a");
+        type_text(&mut stack, 1, "b");
+        stack.undo();
+        type_text(&mut stack, 1, "c");
+        assert_eq!(stack.redo(), None);
+        assert_eq!(stack.undo(), Some(vec![delete(1, "c")]));
+        assert_eq!(stack.undo(), None);
+    }
+
+    #[test]
+    fn suspended_edits_are_not_recorded() {
+        let mut stack = UndoStack::new();
+        stack.set_suspended(true);
+        type_text(&mut stack, 0, "a");
+        stack.set_suspended(false);
+        assert_eq!(stack.undo(), None);
+    }
+
+    #[test]
+    fn clear_ends_open_groups() {
+        let mut stack = UndoStack::new();
+        stack.begin_group();
+        stack.record(insert(0, "a"));
+        stack.clear();
+        stack.record(insert(0, "b"));
+        assert_eq!(stack.undo(), Some(vec![delete(0, "b")]));
+    }
+
+    #[test]
+    fn save_point_follows_undo_and_redo() {
+        let mut stack = UndoStack::new();
+        assert!(stack.is_saved());
+        type_text(&mut stack, 0, "ab");
+        stack.mark_saved();
+        type_text(&mut stack, 2, "c");
+        assert!(!stack.is_saved());
+        assert_eq!(stack.undo(), Some(vec![delete(2, "c")]));
+        assert!(stack.is_saved());
+        stack.undo();
+        assert!(!stack.is_saved());
+        stack.redo();
+        assert!(stack.is_saved());
+    }
+
+    #[test]
+    fn save_point_is_lost_with_redo() {
+        let mut stack = UndoStack::new();
+        type_text(&mut stack, 0, "a");
+        stack.mark_saved();
+        stack.undo();
+        type_text(&mut stack, 0, "b");
+        stack.undo();
+        assert!(!stack.is_saved());
+    }
+
+    #[test]
+    fn cleared_stack_has_no_save_point() {
+        let mut stack = UndoStack::new();
+        stack.clear();
+        assert!(!stack.is_saved());
+        stack.mark_saved();
+        assert!(stack.is_saved());
+    }
+}
@@ -1,7 +1,7 @@
 extern crate gtk;
 extern crate gio;
 extern crate gdk;
-extern crate gdk_pixbuf;
+extern crate glib;
 
 use gtk::prelude::*;
 use gtk::{
@@ -10,21 +10,78 @@ use gtk::{
 };
 
 use gio::{
-    SimpleActionExt, ActionMapExt
+    SimpleActionExt, ActionMapExt, FileExt
 };
 
-use std::path::Path;
+use glib::ToVariant;
+
 use std::ops::Deref;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
-use page::{Page, Pages, PageExtend, PagesExtend};
-
+use page::{Page, Pages, PageExtend, PagesExtend, show_error};
+use eol::LineEnding;
+use search::{self, Query};
+use results_panel::{ResultsPanel, SearchResult};
+use session;
+use recent;
+use status_bar::StatusBar;
+use palette::CommandPalette;
+use location::Position;
+use syntax;
+use prefs::{self, Preferences};
+use keymap::{self, Chord};
+use resources;
+use registry::{Registry, OpenTarget};
+use lines::LineOp;
+
+
+const LINE_ACTIONS: &[(&str, LineOp)] = &[
+    ("duplicate_line", LineOp::Duplicate),
+    ("delete_line", LineOp::Delete),
+    ("move_line_up", LineOp::MoveUp),
+    ("move_line_down", LineOp::MoveDown),
+    ("join_lines", LineOp::Join),
+    ("sort_lines", LineOp::Sort),
+    ("sort_lines_numeric", LineOp::SortNumeric),
+    ("sort_lines_reverse", LineOp::SortReverse),
+    ("unique_lines", LineOp::Unique),
+    ("reverse_lines", LineOp::Reverse),
+];
 
 pub struct WindowCore {
     win: gtk::ApplicationWindow,
     notebook: gtk::Notebook,
     pages: Pages,
-    active_page: Option<Page>,
+    results_panel: ResultsPanel,
+    status_bar: StatusBar,
+    palette: CommandPalette,
+    closing: bool,
+    dialogs: Rc<dyn DialogRunner>,
+}
+
+// Runs the modal dialogs a window and its pages ask questions with. Tests
+// put in their own to answer them without waiting for a click.
+pub trait DialogRunner {
+    fn run(&self, dialog: &gtk::Dialog) -> i32;
+    // The chosen file, None when the chooser was cancelled.
+    fn choose_file(&self, dialog: &gtk::FileChooserDialog) -> Option<PathBuf>;
+}
+
+pub struct GtkDialogs;
+
+impl DialogRunner for GtkDialogs {
+    fn run(&self, dialog: &gtk::Dialog) -> i32 {
+        dialog.run()
+    }
+
+    fn choose_file(&self, dialog: &gtk::FileChooserDialog) -> Option<PathBuf> {
+        if dialog.run() == gtk::ResponseType::Accept.into() {
+            dialog.get_filename()
+        } else {
+            None
+        }
+    }
 }
 
 
@@ -39,23 +96,36 @@ pub trait WindowExtend {
     fn init(&self);
     fn win(&self) -> gtk::ApplicationWindow;
     fn pages(&self) -> Pages;
-    fn page_num(&self, page: Page) -> Option<u32>;
     fn notebook(&self) -> gtk::Notebook;
     fn get_page(&self, file: &gio::File) -> Option<Page>;
     fn open(&self, file: &gio::File, wins: Windows, warning: bool);
+    fn open_file(&self, file: &gio::File, wins: Windows);
     fn close(&self);
+    fn dialogs(&self) -> Rc<dyn DialogRunner>;
+    fn set_dialogs(&self, dialogs: Rc<dyn DialogRunner>);
     fn get_empty_page(&self) -> Option<Page>;
     fn get_active_page(&self) -> Option<Page>;
-    fn set_active_page(&self, page: Option<Page>);
+    fn sync_page_state(&self, page: &Page);
+    fn set_action_state(&self, name: &str, state: &glib::Variant);
+    fn update_status(&self, page: &Page);
+    fn show_goto(&self);
+    fn goto(&self, pos: Position);
+    fn show_results_panel(&self);
+    fn hide_results_panel(&self);
+    fn update_results(&self, wins: Windows);
+    fn activate_result(&self, i: usize);
     fn show_about(&self);
 }
 
 impl WindowExtend for Window {
     fn create(app: &gtk::Application, wins: Windows) -> Window {
-        let builder = gtk::Builder::new_from_file(Path::new("/usr/share/vanilla_text/ui/window.ui"));
+        let builder = resources::builder("window.ui");
         let window: gtk::ApplicationWindow = builder.get_object("window").unwrap();
         window.set_application(Some(app));
 
+        let prefs = prefs::get();
+        window.set_default_size(prefs.window_width, prefs.window_height);
+
         let notebook: gtk::Notebook = builder.get_object("notebook").unwrap();
 
         let pages = Pages::create();
@@ -66,9 +136,17 @@ impl WindowExtend for Window {
                     win: window.clone(),
                     notebook: notebook.clone(),
                     pages: pages.clone(),
-                    active_page: None
+                    results_panel: ResultsPanel::new(&builder),
+                    status_bar: StatusBar::new(&builder),
+                    palette: CommandPalette::new(&builder),
+                    closing: false,
+                    dialogs: Rc::new(GtkDialogs),
                 }));
 
+        win.borrow().results_panel.connect(&win, wins.clone());
+        win.borrow().status_bar.connect(&win);
+        win.borrow().palette.connect(&win);
+
         win.create_new_page(wins.clone());
 
         win.init_actions(wins.clone());
@@ -77,7 +155,10 @@ impl WindowExtend for Window {
             let pages = pages.clone();
             let window = window.clone();
             let win = win.clone();
+            let wins = wins.clone();
             notebook.connect_page_removed(move |_, _, n| {
+                let removed = pages.borrow()[n as usize].clone();
+                removed.discard_snapshot();
                 pages.remove(n as usize);
                 let len = pages.len();
                 if len > 0 {
@@ -90,6 +171,9 @@ impl WindowExtend for Window {
                         window.set_title(&s);
                     }
                 } else {
+                    if !win.borrow().closing {
+                        session::save(&wins);
+                    }
                     win.close();
                 }
             });
@@ -98,10 +182,14 @@ impl WindowExtend for Window {
         {
             let pages = pages.clone();
             let window = window.clone();
+            let win = win.clone();
             notebook.connect_switch_page(move |_, _, n| {
-                if let Some(s) = pages.borrow()[n as usize].tab_label().get_text() {
+                let p = pages.borrow()[n as usize].clone();
+                if let Some(s) = p.tab_label().get_text() {
                     window.set_title(&s);
                 }
+                win.sync_page_state(&p);
+                p.check_file();
             });
         }
 
@@ -111,11 +199,17 @@ impl WindowExtend for Window {
             let win = win.clone();
             let notebook = notebook.clone();
             window.connect_delete_event(move |_, _| {
+                if wins.borrow().iter().all(|w| Rc::ptr_eq(w, &win) || w.pages().len() == 0) {
+                    session::save(&wins);
+                }
+
+                win.borrow_mut().closing = true;
                 loop {
                     let p = pages.borrow_mut().pop();
                     if let Some(p) = p {
                         if p.save_confirm(wins.clone(), win.clone()) {
                             pages.borrow_mut().push(p);
+                            win.borrow_mut().closing = false;
                             return Inhibit(true);
                         } else {
                             pages.borrow_mut().push(p.clone());
@@ -130,6 +224,62 @@ impl WindowExtend for Window {
             });
         }
 
+        {
+            let pending: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+            window.connect_key_press_event(move |window, event| {
+                let key = match keymap::event_key(event) {
+                    Some(key) => key,
+                    None => return Inhibit(false),
+                };
+                let mut keys = pending.borrow().clone();
+                keys.push(key);
+
+                match keymap::lookup_chord(&keys) {
+                    Chord::Prefix => {
+                        *pending.borrow_mut() = keys;
+                        Inhibit(true)
+                    },
+                    Chord::Complete(action) => {
+                        pending.borrow_mut().clear();
+                        if let Some(app) = window.get_application() {
+                            keymap::activate(&app, window, &action);
+                        }
+                        Inhibit(true)
+                    },
+                    Chord::NoMatch => {
+                        let in_chord = keys.len() > 1;
+                        pending.borrow_mut().clear();
+                        Inhibit(in_chord)
+                    },
+                }
+            });
+        }
+
+        {
+            let pages = pages.clone();
+            window.connect_focus_in_event(move |_, _| {
+                let pages: Vec<Page> = pages.borrow().clone();
+                for p in &pages {
+                    p.check_file();
+                }
+                Inhibit(false)
+            });
+        }
+
+        // Hidden pages are checked when they are shown or the window
+        // regains focus, so only the visible one is polled.
+        {
+            let pages = pages.clone();
+            let notebook = notebook.clone();
+            gtk::timeout_add_seconds(2, move || {
+                let pages: Vec<Page> = pages.borrow().clone();
+                if let Some(p) = notebook.get_current_page().and_then(|n| pages.get(n as usize)) {
+                    p.check_file();
+                }
+                Continue(!pages.is_empty())
+            });
+        }
+
         win.register(wins);
 
         win
@@ -139,6 +289,31 @@ impl WindowExtend for Window {
         let page = self.borrow().pages.create_new_page(wins, self.clone());
         let notebook = self.notebook();
         notebook.append_page(&page.contents(), Some(&page.tab()));
+
+        let buf = page.text_view().get_buffer().unwrap();
+        {
+            let win = self.clone();
+            let p = page.clone();
+            buf.connect_mark_set(move |_, _, _| {
+                win.update_status(&p);
+            });
+        }
+        {
+            let win = self.clone();
+            let p = page.clone();
+            buf.connect_changed(move |_| {
+                win.update_status(&p);
+            });
+        }
+        {
+            let win = self.clone();
+            let p = page.clone();
+            page.text_view().connect_property_overwrite_notify(move |text_view| {
+                win.set_action_state("overwrite", &text_view.get_overwrite().to_variant());
+                win.update_status(&p);
+            });
+        }
+
         page
     }
 
@@ -157,7 +332,9 @@ impl WindowExtend for Window {
             let win = self.clone();
             let wins = wins.clone();
             saveas_action.connect_activate(move |_, _| {
-                win.get_active_page().unwrap().save_as(wins.clone(), win.clone());
+                let p = win.get_active_page().unwrap();
+                p.save_as(wins.clone(), win.clone());
+                win.sync_page_state(&p);
             });
         }
 
@@ -182,6 +359,46 @@ impl WindowExtend for Window {
             });
         }
 
+        let undo_action = gio::SimpleAction::new("undo", None);
+        {
+            let win = self.clone();
+            undo_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().undo();
+            });
+        }
+
+        let redo_action = gio::SimpleAction::new("redo", None);
+        {
+            let win = self.clone();
+            redo_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().redo();
+            });
+        }
+
+        let find_action = gio::SimpleAction::new("find", None);
+        {
+            let win = self.clone();
+            find_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().show_find_bar(false);
+            });
+        }
+
+        let replace_action = gio::SimpleAction::new("replace", None);
+        {
+            let win = self.clone();
+            replace_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().show_find_bar(true);
+            });
+        }
+
+        let find_all_action = gio::SimpleAction::new("find_all", None);
+        {
+            let win = self.clone();
+            find_all_action.connect_activate(move |_, _| {
+                win.show_results_panel();
+            });
+        }
+
         let selectall_action = gio::SimpleAction::new("selectall", None);
         {
             let win = self.clone();
@@ -200,7 +417,9 @@ impl WindowExtend for Window {
                 let p = win.get_active_page().unwrap();
                 let text_view = &p.text_view();
                 let clipboard = text_view.get_clipboard(&gdk::SELECTION_CLIPBOARD);
-                text_view.get_buffer().unwrap().copy_clipboard(&clipboard);
+                if !p.copy_block(&clipboard) {
+                    text_view.get_buffer().unwrap().copy_clipboard(&clipboard);
+                }
             });
         }
 
@@ -218,7 +437,9 @@ impl WindowExtend for Window {
                     buf = text_view.get_buffer().unwrap();
                     editable = text_view.get_editable();
                 }
-                buf.paste_clipboard(&clipboard, None, editable);
+                if !p.paste_block(&clipboard) && !p.paste_carets(&clipboard) {
+                    buf.paste_clipboard(&clipboard, None, editable);
+                }
             });
         }
 
@@ -236,31 +457,185 @@ impl WindowExtend for Window {
                     buf = text_view.get_buffer().unwrap();
                     editable = text_view.get_editable();
                 }
-                buf.cut_clipboard(&clipboard, editable);
+                if !p.cut_block(&clipboard) {
+                    buf.cut_clipboard(&clipboard, editable);
+                }
 
                 let text_view = &p.text_view();
                 text_view.scroll_mark_onscreen(&buf.get_insert().unwrap());
             });
         }
 
+        let add_next_occurrence_action = gio::SimpleAction::new("add_next_occurrence", None);
+        {
+            let win = self.clone();
+            add_next_occurrence_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().add_next_occurrence();
+            });
+        }
+
+        let add_cursors_to_lines_action = gio::SimpleAction::new("add_cursors_to_lines", None);
+        {
+            let win = self.clone();
+            add_cursors_to_lines_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().add_carets_to_lines();
+            });
+        }
+
+        let jump_to_bracket_action = gio::SimpleAction::new("jump_to_bracket", None);
+        {
+            let win = self.clone();
+            jump_to_bracket_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().jump_to_bracket();
+            });
+        }
+
+        let select_to_bracket_action = gio::SimpleAction::new("select_to_bracket", None);
+        {
+            let win = self.clone();
+            select_to_bracket_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().select_to_bracket();
+            });
+        }
+
         let open_action = gio::SimpleAction::new("open", None);
         {
             use win::run_file_chooser_dialog;
             let wins = wins.clone();
             let win = self.clone();
             open_action.connect_activate(move |_, _| {
-                if let Some(file) = run_file_chooser_dialog() {
-                    if let Some(p) = win.get_page(&file) {
-                        win.present(p);
-                    } else if wins.get_page(&file).is_some() {
-                        win.open(&file, wins.clone(), true);
+                if let Some(file) = run_file_chooser_dialog(&win) {
+                    win.open_file(&file, wins.clone());
+                }
+            });
+        }
+
+        let open_recent_action = gio::SimpleAction::new("open_recent", glib::VariantTy::new("s").ok());
+        {
+            let wins = wins.clone();
+            let win = self.clone();
+            open_recent_action.connect_activate(move |_, uri| {
+                if let Some(uri) = uri.as_ref().and_then(|v| v.get_str()) {
+                    let file = gio::File::new_for_uri(uri);
+                    if file.query_exists(None) {
+                        win.open_file(&file, wins.clone());
                     } else {
-                        win.open(&file, wins.clone(), false);
+                        recent::remove(uri);
+                        let dialog = gtk::MessageDialog::new(Some(&win.win()),
+                                                             gtk::DIALOG_MODAL,
+                                                             gtk::MessageType::Warning,
+                                                             gtk::ButtonsType::Close,
+                                                             "Error: The file no longer exists");
+                        win.dialogs().run(&dialog.clone().upcast());
+                        dialog.destroy();
                     }
                 }
             });
         }
 
+        let reopen_with_encoding_action = gio::SimpleAction::new("reopen_with_encoding", None);
+        {
+            let win = self.clone();
+            reopen_with_encoding_action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().reopen_with_encoding(win.clone());
+            });
+        }
+
+        let save_with_encoding_action = gio::SimpleAction::new("save_with_encoding", None);
+        {
+            let win = self.clone();
+            let wins = wins.clone();
+            save_with_encoding_action.connect_activate(move |_, _| {
+                let p = win.get_active_page().unwrap();
+                p.save_with_encoding(wins.clone(), win.clone());
+                win.sync_page_state(&p);
+            });
+        }
+
+        let line_ending_action = gio::SimpleAction::new_stateful("line_ending",
+                                                                 glib::VariantTy::new("s").ok(),
+                                                                 &LineEnding::default().id().to_variant());
+        {
+            let win = self.clone();
+            line_ending_action.connect_change_state(move |action, value| {
+                if let Some(eol) = value.as_ref().and_then(|v| v.get_str()).and_then(LineEnding::from_id) {
+                    let p = win.get_active_page().unwrap();
+                    p.set_line_ending(eol);
+                    action.set_state(&eol.id().to_variant());
+                    win.update_status(&p);
+                }
+            });
+        }
+
+        let language_action = gio::SimpleAction::new_stateful("language",
+                                                              glib::VariantTy::new("s").ok(),
+                                                              &"".to_variant());
+        {
+            let win = self.clone();
+            language_action.connect_change_state(move |action, value| {
+                if let Some(id) = value.as_ref().and_then(|v| v.get_str()) {
+                    let p = win.get_active_page().unwrap();
+                    p.set_language(syntax::for_id(id));
+                    action.set_state(&id.to_variant());
+                    win.update_status(&p);
+                }
+            });
+        }
+
+        let indent_width_action = gio::SimpleAction::new_stateful("indent_width",
+                                                                  glib::VariantTy::new("s").ok(),
+                                                                  &prefs::get().tab_width.to_string().to_variant());
+        {
+            let win = self.clone();
+            indent_width_action.connect_change_state(move |action, value| {
+                let width = value.as_ref().and_then(|v| v.get_str()).and_then(|s| s.parse::<u32>().ok())
+                    .filter(|w| (1..=prefs::MAX_TAB_WIDTH).contains(w));
+                if let Some(width) = width {
+                    let p = win.get_active_page().unwrap();
+                    p.set_indent_width(width);
+                    action.set_state(&width.to_string().to_variant());
+                    win.update_status(&p);
+                }
+            });
+        }
+
+        let indent_spaces_action = gio::SimpleAction::new_stateful("indent_spaces", None, &false.to_variant());
+        {
+            let win = self.clone();
+            indent_spaces_action.connect_activate(move |action, _| {
+                let p = win.get_active_page().unwrap();
+                let spaces = !p.indent_spaces();
+                p.set_indent_spaces(spaces);
+                action.set_state(&spaces.to_variant());
+                win.update_status(&p);
+            });
+        }
+
+        let overwrite_action = gio::SimpleAction::new_stateful("overwrite", None, &false.to_variant());
+        {
+            let win = self.clone();
+            overwrite_action.connect_activate(move |_, _| {
+                let text_view = win.get_active_page().unwrap().text_view();
+                text_view.set_overwrite(!text_view.get_overwrite());
+            });
+        }
+
+        let goto_line_action = gio::SimpleAction::new("goto_line", None);
+        {
+            let win = self.clone();
+            goto_line_action.connect_activate(move |_, _| {
+                win.show_goto();
+            });
+        }
+
+        let command_palette_action = gio::SimpleAction::new("command_palette", None);
+        {
+            let win = self.clone();
+            command_palette_action.connect_activate(move |_, _| {
+                win.borrow().palette.show(&win);
+            });
+        }
+
         let about_action = gio::SimpleAction::new("about", None);
         {
             let win = self.clone();
@@ -274,12 +649,40 @@ impl WindowExtend for Window {
         w.add_action(&saveas_action);
         w.add_action(&close_tab_action);
         w.add_action(&new_tab_action);
+        w.add_action(&undo_action);
+        w.add_action(&redo_action);
+        w.add_action(&find_action);
+        w.add_action(&replace_action);
+        w.add_action(&find_all_action);
         w.add_action(&selectall_action);
         w.add_action(&copy_action);
         w.add_action(&paste_action);
         w.add_action(&cut_action);
+        w.add_action(&add_next_occurrence_action);
+        w.add_action(&add_cursors_to_lines_action);
+        w.add_action(&jump_to_bracket_action);
+        w.add_action(&select_to_bracket_action);
         w.add_action(&open_action);
+        w.add_action(&open_recent_action);
+        w.add_action(&reopen_with_encoding_action);
+        w.add_action(&save_with_encoding_action);
+        w.add_action(&line_ending_action);
+        w.add_action(&language_action);
+        w.add_action(&indent_width_action);
+        w.add_action(&indent_spaces_action);
+        w.add_action(&overwrite_action);
+        w.add_action(&goto_line_action);
+        w.add_action(&command_palette_action);
         w.add_action(&about_action);
+
+        for &(name, op) in LINE_ACTIONS {
+            let action = gio::SimpleAction::new(name, None);
+            let win = self.clone();
+            action.connect_activate(move |_, _| {
+                win.get_active_page().unwrap().edit_lines(op);
+            });
+            w.add_action(&action);
+        }
     }
 
 
@@ -320,29 +723,57 @@ impl WindowExtend for Window {
         self.borrow().notebook.clone()
     }
 
-    fn page_num(&self, page: Page) -> Option<u32> {
-        self.borrow().notebook.page_num(&page.contents())
-    }
-
     fn open(&self, file: &gio::File, wins: Windows, warning: bool) {
         let page;
+        let created;
         if let Some(p) = self.get_empty_page() {
             page = p;
+            created = false;
         } else {
             page = self.create_new_page(wins);
+            created = true;
         }
 
-        page.load_file(file);
+        if let Err(e) = page.load_file(file) {
+            if created {
+                self.notebook().detach_tab(&page.contents());
+            }
+            show_error(self, &e);
+            return;
+        }
         if warning { page.show_warning(); }
+        self.sync_page_state(&page);
         let n = self.notebook().page_num(&page.contents());
         self.notebook().set_current_page(n);
         self.win().set_title(&page.tab_label().get_text().unwrap());
     }
 
+    fn open_file(&self, file: &gio::File, wins: Windows) {
+        let uri = file.get_uri().unwrap_or_default();
+        let index = wins.borrow().iter().position(|w| Rc::ptr_eq(w, self)).unwrap_or(usize::max_value());
+        let target = wins.registry().open_target(index, &uri);
+        match target {
+            OpenTarget::Present(i) => {
+                let p = self.pages().borrow()[i].clone();
+                self.present(p);
+            },
+            OpenTarget::Duplicate => self.open(file, wins.clone(), true),
+            OpenTarget::New => self.open(file, wins.clone(), false),
+        }
+    }
+
     fn close(&self) {
         self.borrow().win.destroy();
     }
 
+    fn dialogs(&self) -> Rc<dyn DialogRunner> {
+        self.borrow().dialogs.clone()
+    }
+
+    fn set_dialogs(&self, dialogs: Rc<dyn DialogRunner>) {
+        self.borrow_mut().dialogs = dialogs;
+    }
+
     fn get_empty_page(&self) -> Option<Page> {
         let pages = self.pages();
         for p in pages.borrow().deref() {
@@ -363,8 +794,96 @@ impl WindowExtend for Window {
         None
     }
 
-    fn set_active_page(&self, page: Option<Page>) {
-        self.borrow_mut().active_page = page;
+    fn sync_page_state(&self, page: &Page) {
+        let language = page.language().map_or(String::new(), |l| l.id.clone());
+        self.set_action_state("line_ending", &page.line_ending().id().to_variant());
+        self.set_action_state("language", &language.to_variant());
+        self.set_action_state("indent_width", &page.indent_width().to_string().to_variant());
+        self.set_action_state("indent_spaces", &page.indent_spaces().to_variant());
+        self.set_action_state("overwrite", &page.text_view().get_overwrite().to_variant());
+        self.borrow().status_bar.update(page);
+    }
+
+    fn set_action_state(&self, name: &str, state: &glib::Variant) {
+        if let Some(action) = self.win().lookup_action(name) {
+            if let Ok(action) = action.downcast::<gio::SimpleAction>() {
+                action.set_state(state);
+            }
+        }
+    }
+
+    // Page signals also fire for background tabs, which must not overwrite
+    // the status of the one being shown.
+    fn update_status(&self, page: &Page) {
+        let active = self.notebook().get_current_page()
+            .and_then(|i| self.pages().borrow().get(i as usize).cloned());
+        if active.is_some_and(|a| Rc::ptr_eq(&a, page)) {
+            self.borrow().status_bar.update(page);
+        }
+    }
+
+    fn show_goto(&self) {
+        if let Some(p) = self.get_active_page() {
+            self.borrow().status_bar.show_goto(&p);
+        }
+    }
+
+    fn goto(&self, pos: Position) {
+        if let Some(p) = self.get_active_page() {
+            p.goto(pos);
+            p.text_view().grab_focus();
+        }
+    }
+
+    fn show_results_panel(&self) {
+        self.borrow().results_panel.show();
+    }
+
+    fn hide_results_panel(&self) {
+        self.borrow().results_panel.hide();
+        if let Some(p) = self.get_active_page() {
+            p.text_view().grab_focus();
+        }
+    }
+
+    fn update_results(&self, wins: Windows) {
+        let query = self.borrow().results_panel.query();
+        if query.is_empty() {
+            let panel = &self.borrow().results_panel;
+            panel.set_results(Vec::new());
+            panel.set_status("", false);
+            return;
+        }
+
+        match wins.find_all(&query) {
+            Ok(results) => {
+                let status = match results.len() {
+                    0 => "No matches".to_string(),
+                    1 => "1 match".to_string(),
+                    n => format!("{} matches", n),
+                };
+                let panel = &self.borrow().results_panel;
+                panel.set_results(results);
+                panel.set_status(&status, false);
+            },
+            Err(_) => {
+                let panel = &self.borrow().results_panel;
+                panel.set_results(Vec::new());
+                panel.set_status("Invalid pattern", true);
+            },
+        }
+    }
+
+    fn activate_result(&self, i: usize) {
+        let result = self.borrow().results_panel.result(i);
+        if let Some((win, page, start, end)) = result {
+            if !win.pages().borrow().iter().any(|p| Rc::ptr_eq(p, &page)) {
+                return;
+            }
+            win.present(page.clone());
+            page.select_offsets(start, end);
+            page.text_view().grab_focus();
+        }
     }
 
     fn show_about(&self) {
@@ -373,8 +892,7 @@ impl WindowExtend for Window {
         dialog.set_transient_for(&self.win());
         dialog.set_program_name("Vanilla Text");
 
-        let logo = gdk_pixbuf::Pixbuf::new_from_file("/usr/share/icons/hicolor/128x128/apps/vanilla_text.png");
-        if let Ok(logo) = logo {
+        if let Some(logo) = resources::pixbuf("icon_128x128.png") {
             dialog.set_logo(Some(&logo));
         }
 
@@ -386,33 +904,62 @@ impl WindowExtend for Window {
 pub type Windows = Rc<RefCell<Vec<Window>>>;
 
 pub trait WindowsExtend {
+    fn registry(&self) -> Registry;
+    fn locate(&self, page: &Page) -> Option<(usize, usize)>;
     fn get_page(&self, file: &gio::File) -> Option<Page>;
-    fn destroy(&self, win: Window);
+    fn find_all(&self, query: &Query) -> Result<Vec<SearchResult>, String>;
     fn get_active_window(&self, app: &gtk::Application) -> Option<Window>;
+    fn apply_prefs(&self, prefs: &Preferences);
 }
 
 impl WindowsExtend for Windows {
-    fn get_page(&self, file: &gio::File) -> Option<Page> {
+    fn registry(&self) -> Registry {
+        let mut registry = Registry::new();
         for w in self.borrow().deref() {
-            if let Some(p) = w.pages().get_page(file) {
-                return Some(p.clone());
+            registry.add_window(w.pages().uris());
+        }
+        registry
+    }
+
+    fn locate(&self, page: &Page) -> Option<(usize, usize)> {
+        for (i, w) in self.borrow().iter().enumerate() {
+            if let Some(j) = w.pages().borrow().iter().position(|p| Rc::ptr_eq(p, page)) {
+                return Some((i, j));
             }
         }
 
-        return None;
+        None
     }
 
-    fn destroy(&self, win: Window) {
-        let win = win.clone();
-        let i = self.borrow().iter().position(move |w| {
-            w.borrow().win == win.borrow().win
-        }).unwrap();
+    fn get_page(&self, file: &gio::File) -> Option<Page> {
+        let uri = file.get_uri()?;
+        self.registry().find(&uri).map(|(w, p)| self.borrow()[w].pages().borrow()[p].clone())
+    }
 
-        {
-            let v = self.borrow();
-            v[i].borrow().win.destroy();
+    fn find_all(&self, query: &Query) -> Result<Vec<SearchResult>, String> {
+        let matcher = query.matcher()?;
+        let mut results = Vec::new();
+
+        for w in self.borrow().deref() {
+            for p in w.pages().borrow().deref() {
+                let text = p.text();
+                let tab = p.tab_label().get_text().unwrap_or_default();
+                let matches = matcher.find_all(&text);
+                for m in search::line_matches(&text, &matches) {
+                    results.push(SearchResult {
+                        win: w.clone(),
+                        page: p.clone(),
+                        tab: tab.clone(),
+                        line: m.line,
+                        start: m.range.start,
+                        end: m.range.end,
+                        preview: m.preview,
+                    });
+                }
+            }
         }
-        self.borrow_mut().remove(i);
+
+        Ok(results)
     }
 
     fn get_active_window(&self, app: &gtk::Application) -> Option<Window> {
@@ -425,28 +972,29 @@ impl WindowsExtend for Windows {
 
         return None;
     }
+
+    fn apply_prefs(&self, prefs: &Preferences) {
+        for w in self.borrow().deref() {
+            for p in w.pages().borrow().deref() {
+                p.apply_prefs(prefs);
+            }
+            if let Some(p) = w.get_active_page() {
+                w.sync_page_state(&p);
+            }
+        }
+    }
 }
 
-fn run_file_chooser_dialog() -> Option<gio::File> {
+fn run_file_chooser_dialog(win: &Window) -> Option<gio::File> {
     let dialog = gtk::FileChooserDialog::new::<gtk::Window>(Some("Open File"),
                                      None,
                                      gtk::FileChooserAction::Open);
     dialog.add_button("Cancel", gtk::ResponseType::Cancel.into());
     dialog.add_button("Open", gtk::ResponseType::Accept.into());
 
-    let file;
-    if dialog.run() == gtk::ResponseType::Accept.into() {
-        if let Some(path) = dialog.get_filename() {
-            file = Some(gio::File::new_for_path(path.as_path()))
-        } else {
-            file = None
-        }
-    } else {
-        file = None
-    }
+    let file = win.dialogs().choose_file(&dialog).map(|path| gio::File::new_for_path(path.as_path()));
 
     dialog.destroy();
 
     file
 }
-
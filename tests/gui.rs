@@ -0,0 +1,368 @@
+extern crate vanilla_text;
+extern crate gtk;
+extern crate gio;
+
+use gtk::{
+    GtkApplicationExt, WindowExt, WidgetExt, NotebookExtManual,
+    LabelExt, TextViewExt, TextBufferExt, ResponseType
+};
+use gio::{ApplicationExt, ActionGroupExt};
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::thread;
+
+use vanilla_text::app::{build, APP_ID};
+use vanilla_text::win::{DialogRunner, Window, WindowExtend, Windows, WindowsExtend};
+use vanilla_text::page::{Page, PageExtend, PagesExtend};
+use vanilla_text::resources;
+use vanilla_text::prefs;
+
+// These tests drive the real application and need a display, e.g.
+//
+//     xvfb-run -a cargo test
+//     GDK_BACKEND=broadway broadwayd :5 & BROADWAY_DISPLAY=:5 cargo test
+//
+// GTK may only be used from the thread that initialized it, so this file
+// runs its own harness (`harness = false`) that runs the scenarios one
+// after another on the main thread. Arguments filter them by name.
+const TESTS: &[(&str, fn())] = &[
+    ("activate_opens_untitled_window", activate_opens_untitled_window),
+    ("new_tab_adds_page", new_tab_adds_page),
+    ("save_writes_file_and_renames_tab", save_writes_file_and_renames_tab),
+    ("close_tab_asks_before_discarding_changes", close_tab_asks_before_discarding_changes),
+    ("closing_last_tab_closes_window", closing_last_tab_closes_window),
+    ("delete_event_keeps_window_on_cancel", delete_event_keeps_window_on_cancel),
+    ("open_same_file_twice", open_same_file_twice),
+    ("save_as_refuses_file_open_elsewhere", save_as_refuses_file_open_elsewhere),
+    ("open_missing_file_shows_error", open_missing_file_shows_error),
+];
+
+fn main() {
+    let filters: Vec<String> = env::args().skip(1).filter(|a| !a.starts_with('-')).collect();
+    let tests: Vec<_> = TESTS.iter()
+        .filter(|&&(name, _)| filters.is_empty() || filters.iter().any(|f| name.contains(f.as_str())))
+        .collect();
+
+    let root = env::temp_dir().join(format!("vanilla_text-gui-{}", process::id()));
+    for &(var, dir) in &[("XDG_CONFIG_HOME", "config"), ("XDG_DATA_HOME", "data"),
+                         ("XDG_STATE_HOME", "state"), ("XDG_CACHE_HOME", "cache")] {
+        env::set_var(var, root.join(dir));
+    }
+
+    if gtk::init().is_err() {
+        eprintln!("error: the GUI tests cannot open a display; run them under one, e.g. `xvfb-run -a cargo test`");
+        process::exit(1);
+    }
+    resources::register();
+
+    println!("\nrunning {} tests", tests.len());
+    let mut failed = Vec::new();
+    for &&(name, test) in &tests {
+        let passed = panic::catch_unwind(AssertUnwindSafe(test)).is_ok();
+        println!("test {} ... {}", name, if passed { "ok" } else { "FAILED" });
+        if !passed {
+            failed.push(name);
+        }
+    }
+    let _ = fs::remove_dir_all(&root);
+
+    if !failed.is_empty() {
+        println!("\nfailures:");
+        for name in &failed {
+            println!("    {}", name);
+        }
+    }
+    println!("\ntest result: {}. {} passed; {} failed; {} filtered out\n",
+             if failed.is_empty() { "ok" } else { "FAILED" },
+             tests.len() - failed.len(), failed.len(), TESTS.len() - tests.len());
+    if !failed.is_empty() {
+        process::exit(101);
+    }
+}
+
+// Answers the dialogs a test expects, in the order they will appear. An
+// unexpected dialog is cancelled and fails the test when it ends, since
+// panicking inside a GTK signal handler would abort the whole run.
+#[derive(Default)]
+struct StubDialogs {
+    responses: RefCell<VecDeque<ResponseType>>,
+    files: RefCell<VecDeque<Option<PathBuf>>>,
+    unexpected: Cell<usize>,
+}
+
+impl StubDialogs {
+    fn push_response(&self, response: ResponseType) {
+        self.responses.borrow_mut().push_back(response);
+    }
+
+    // None cancels the file chooser.
+    fn push_file(&self, path: Option<PathBuf>) {
+        self.files.borrow_mut().push_back(path);
+    }
+
+    fn check(&self) {
+        assert_eq!(self.unexpected.get(), 0, "a dialog was shown without a queued answer");
+        assert!(self.responses.borrow().is_empty() && self.files.borrow().is_empty(),
+                "a queued dialog answer was never used");
+    }
+}
+
+impl DialogRunner for StubDialogs {
+    fn run(&self, _: &gtk::Dialog) -> i32 {
+        self.responses.borrow_mut().pop_front().unwrap_or_else(|| {
+            self.unexpected.set(self.unexpected.get() + 1);
+            ResponseType::Cancel
+        }).into()
+    }
+
+    fn choose_file(&self, _: &gtk::FileChooserDialog) -> Option<PathBuf> {
+        self.files.borrow_mut().pop_front().unwrap_or_else(|| {
+            self.unexpected.set(self.unexpected.get() + 1);
+            None
+        })
+    }
+}
+
+struct Harness {
+    app: gtk::Application,
+    wins: Windows,
+    dir: PathBuf,
+    dialogs: Rc<StubDialogs>,
+}
+
+impl Harness {
+    fn start(name: &str) -> Harness {
+        let _ = fs::remove_dir_all(prefs::state_dir());
+        let dir = env::temp_dir().join(format!("vanilla_text-gui-{}", process::id())).join(name);
+        fs::create_dir_all(&dir).unwrap();
+
+        let app = gtk::Application::new(APP_ID, gio::APPLICATION_HANDLES_OPEN | gio::APPLICATION_NON_UNIQUE).unwrap();
+        let wins = build(&app);
+        app.register(None).unwrap();
+        app.activate();
+        flush();
+
+        Harness { app: app, wins: wins, dir: dir, dialogs: Rc::new(StubDialogs::default()) }
+    }
+
+    // Windows answer their dialogs from the stub once fetched here.
+    fn window(&self, i: usize) -> Window {
+        let w = self.wins.borrow()[i].clone();
+        w.set_dialogs(self.dialogs.clone());
+        w
+    }
+
+    fn open_windows(&self) -> usize {
+        self.app.get_windows().len()
+    }
+
+    fn app_action(&self, name: &str) {
+        self.app.activate_action(name, None);
+        flush();
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for w in self.app.get_windows() {
+            w.destroy();
+        }
+        flush();
+        if !thread::panicking() {
+            self.dialogs.check();
+        }
+    }
+}
+
+fn flush() {
+    while gtk::events_pending() {
+        gtk::main_iteration();
+    }
+}
+
+fn win_action(w: &Window, name: &str) {
+    w.win().activate_action(name, None);
+    flush();
+}
+
+fn n_pages(w: &Window) -> u32 {
+    w.notebook().get_n_pages()
+}
+
+fn title(w: &Window) -> String {
+    w.win().get_title().unwrap_or_default()
+}
+
+fn tab_label(p: &Page) -> String {
+    p.tab_label().get_text().unwrap_or_default()
+}
+
+fn type_text(p: &Page, text: &str) {
+    p.text_view().get_buffer().unwrap().set_text(text);
+    flush();
+}
+
+fn activate_opens_untitled_window() {
+    let h = Harness::start("activate");
+    assert_eq!(h.open_windows(), 1);
+
+    let w = h.window(0);
+    assert_eq!(n_pages(&w), 1);
+    assert_eq!(title(&w), "~new file~");
+    assert_eq!(tab_label(&w.get_active_page().unwrap()), "untitled");
+}
+
+fn new_tab_adds_page() {
+    let h = Harness::start("new_tab");
+    let w = h.window(0);
+
+    win_action(&w, "new_tab");
+    win_action(&w, "new_tab");
+    assert_eq!(n_pages(&w), 3);
+    assert_eq!(w.pages().len(), 3);
+}
+
+fn save_writes_file_and_renames_tab() {
+    let h = Harness::start("save");
+    let w = h.window(0);
+    let p = w.get_active_page().unwrap();
+    let path = h.path("a.txt");
+
+    type_text(&p, "hello\nworld\n");
+    assert!(p.changed());
+
+    h.dialogs.push_file(Some(path.clone()));
+    win_action(&w, "save");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello\nworld\n");
+    assert_eq!(tab_label(&p), "a.txt");
+    assert!(!p.changed());
+
+    // Saving again writes to the same file without asking.
+    type_text(&p, "again\n");
+    win_action(&w, "save");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "again\n");
+}
+
+fn close_tab_asks_before_discarding_changes() {
+    let h = Harness::start("close_tab");
+    let w = h.window(0);
+    win_action(&w, "new_tab");
+    type_text(&w.get_active_page().unwrap(), "unsaved");
+
+    h.dialogs.push_response(ResponseType::Cancel);
+    win_action(&w, "close_tab");
+    assert_eq!(n_pages(&w), 2);
+
+    h.dialogs.push_response(ResponseType::Reject);
+    win_action(&w, "close_tab");
+    assert_eq!(n_pages(&w), 1);
+    assert_eq!(w.pages().len(), 1);
+    assert_eq!(title(&w), "untitled");
+    assert_eq!(h.open_windows(), 1);
+}
+
+fn closing_last_tab_closes_window() {
+    let h = Harness::start("close_last");
+    let w = h.window(0);
+
+    win_action(&w, "close_tab");
+    assert_eq!(w.pages().len(), 0);
+    assert_eq!(h.open_windows(), 0);
+}
+
+fn delete_event_keeps_window_on_cancel() {
+    let h = Harness::start("delete_event");
+    let w = h.window(0);
+    let path = h.path("kept.txt");
+    fs::write(&path, "on disk\n").unwrap();
+
+    w.open_file(&gio::File::new_for_path(&path), h.wins.clone());
+    win_action(&w, "new_tab");
+    let p = w.pages().borrow()[1].clone();
+    type_text(&p, "unsaved");
+
+    h.dialogs.push_response(ResponseType::Cancel);
+    w.win().close();
+    flush();
+    assert_eq!(h.open_windows(), 1);
+    assert_eq!(n_pages(&w), 2);
+
+    h.dialogs.push_response(ResponseType::Reject);
+    w.win().close();
+    flush();
+    assert_eq!(h.open_windows(), 0);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "on disk\n");
+}
+
+fn open_same_file_twice() {
+    let h = Harness::start("open_twice");
+    let path = h.path("b.txt");
+    fs::write(&path, "b\n").unwrap();
+    let file = gio::File::new_for_path(&path);
+
+    let w = h.window(0);
+    w.open_file(&file, h.wins.clone());
+    w.open_file(&file, h.wins.clone());
+    assert_eq!(n_pages(&w), 1);
+    assert_eq!(title(&w), "b.txt");
+
+    h.app_action("new_window");
+    assert_eq!(h.open_windows(), 2);
+    let w2 = h.window(1);
+    w2.open_file(&file, h.wins.clone());
+    assert_eq!(n_pages(&w2), 1);
+    assert_eq!(tab_label(&w2.get_active_page().unwrap()), "b.txt");
+
+    let found = h.wins.get_page(&file).unwrap();
+    assert!(found.contents() == w.pages().borrow()[0].contents());
+}
+
+fn save_as_refuses_file_open_elsewhere() {
+    let h = Harness::start("save_as");
+    let path = h.path("c.txt");
+    fs::write(&path, "c\n").unwrap();
+
+    let w = h.window(0);
+    w.open_file(&gio::File::new_for_path(&path), h.wins.clone());
+    win_action(&w, "new_tab");
+    w.notebook().set_current_page(Some(1));
+    flush();
+    type_text(&w.get_active_page().unwrap(), "other");
+
+    h.dialogs.push_file(Some(path.clone()));
+    h.dialogs.push_response(ResponseType::Close);
+    win_action(&w, "saveas");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "c\n");
+    assert_eq!(tab_label(&w.get_active_page().unwrap()), "untitled");
+}
+
+fn open_missing_file_shows_error() {
+    let h = Harness::start("open_missing");
+    let missing = gio::File::new_for_path(h.path("missing.txt"));
+
+    let w = h.window(0);
+    h.dialogs.push_response(ResponseType::Close);
+    w.open_file(&missing, h.wins.clone());
+    assert_eq!(n_pages(&w), 1);
+    assert!(w.get_active_page().unwrap().file().is_none());
+
+    let path = h.path("d.txt");
+    fs::write(&path, "d\n").unwrap();
+    w.open_file(&gio::File::new_for_path(&path), h.wins.clone());
+    h.dialogs.push_response(ResponseType::Close);
+    w.open_file(&missing, h.wins.clone());
+    flush();
+    assert_eq!(n_pages(&w), 1);
+    assert_eq!(title(&w), "d.txt");
+}